@@ -4,6 +4,9 @@ var filesToCache = [
   './index.html',
   './encodec-explorer.js',
   './encodec-explorer_bg.wasm',
+  './decode-worker.js',
+  './decode-worker_bg.wasm',
+  './decode-worker_loader.js',
 ];
 
 /* Start the service worker and cache all of the app's content */
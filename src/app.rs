@@ -1,40 +1,116 @@
 use std::{sync::Arc, time::Duration};
 
 use egui::{emath, epaint, pos2, vec2, Color32, Pos2, Rect, Spinner, Stroke};
-use log::{info, warn};
-use poll_promise::Promise;
+use log::{debug, info, warn};
 
 use crate::{
-    audio,
-    code_ui::{self, Codes},
-    compute::{self, Compute},
-    synth,
+    audio, audio_ui, code_ui,
+    codes::{self, Codes},
+    compute::{self, LoopMode, DEFAULT_WARMUP_FRAMES, MAX_WARMUP_FRAMES},
+    contribution_ui, evolution, files,
+    history::History,
+    latent, patch, resample, share, spectrum_ui, synth, wav,
+    worker::{self, Request, Response, Worker},
 };
 
-#[derive(Default)]
-enum ComputeState {
-    #[default]
-    Uninitialized,
-    Loading(Promise<anyhow::Result<Compute>>),
-    Loaded(Compute),
+/// Longest export, in seconds.
+const MAX_EXPORT_DURATION: f32 = 600.0;
+
+const UNDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+const REDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+const REDO_SHORTCUT_ALT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+
+struct ExportSettings {
+    file_name: String,
+    repeats: usize,
+    device_sample_rate: bool,
+    format: wav::Format,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            file_name: "loop.wav".to_string(),
+            repeats: 1,
+            device_sample_rate: false,
+            format: wav::Format::Int16,
+        }
+    }
+}
+
+struct SaveSettings {
+    file_name: String,
+    format: patch::Format,
+}
+
+impl Default for SaveSettings {
+    fn default() -> Self {
+        Self {
+            file_name: "patch".to_string(),
+            format: patch::Format::Json,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum View {
+    Editor,
+    Evolution,
+    Latent,
 }
 
 pub struct EncodecExplorer {
     codes: Option<Codes>,
-    compute: ComputeState,
+    /// Codes to replace the current ones with at the next update, from a shared url or an encoded wav file.
+    incoming_codes: Option<Codes>,
+    history: History,
+    editor: code_ui::EditorState,
+    view: View,
+    evolution: evolution::Evolution,
+    latent: latent::LatentEditor,
+    spectrogram: spectrum_ui::SpectrogramView,
+    contributions: contribution_ui::ContributionView,
+    worker: Option<Worker>,
     audio: Option<audio::AudioManager>,
     synth: Option<Arc<synth::SamplePlayer>>,
     samples: Vec<f32>,
+    /// Incremented whenever `samples` change.
+    samples_generation: u64,
+    /// Crossfade time in seconds when a new loop arrives.
+    crossfade: f32,
+    loop_mode: LoopMode,
+    error: Option<String>,
+    export: ExportSettings,
+    save: SaveSettings,
 }
 
 impl Default for EncodecExplorer {
     fn default() -> Self {
         Self {
             codes: None,
-            compute: ComputeState::Uninitialized,
+            incoming_codes: None,
+            history: History::default(),
+            editor: code_ui::EditorState::default(),
+            view: View::Editor,
+            evolution: evolution::Evolution::default(),
+            latent: latent::LatentEditor::default(),
+            spectrogram: spectrum_ui::SpectrogramView::default(),
+            contributions: contribution_ui::ContributionView::default(),
+            worker: None,
             audio: None,
             synth: None,
             samples: vec![0.0; 320],
+            samples_generation: 0,
+            crossfade: synth::DEFAULT_CROSSFADE,
+            loop_mode: LoopMode::default(),
+            error: None,
+            export: ExportSettings::default(),
+            save: SaveSettings::default(),
         }
     }
 }
@@ -46,58 +122,328 @@ impl EncodecExplorer {
         });
         Self {
             synth: Some(Arc::new(synth::SamplePlayer::new())),
+            incoming_codes: share::read_codes(),
             ..Default::default()
         }
     }
 }
 
+impl EncodecExplorer {
+    fn handle_response(&mut self, response: Response) {
+        match response {
+            Response::Decoded {
+                codes,
+                mode,
+                samples,
+            } => {
+                if Some(&codes) == self.codes.as_ref() && mode == self.loop_mode {
+                    self.samples = samples;
+                    self.samples_generation += 1;
+                    // don't interrupt auditioning of candidates
+                    if self.view == View::Editor {
+                        self.contributions.stop_audition();
+                        self.synth
+                            .as_ref()
+                            .unwrap()
+                            .update_samples(self.samples.clone());
+                    }
+                } else {
+                    debug!("discarding stale samples");
+                }
+            }
+            Response::DecodedCandidate {
+                generation,
+                index,
+                samples,
+            } => {
+                self.evolution.set_samples(generation, index, samples);
+            }
+            Response::DecodedLatent {
+                latent,
+                mode,
+                samples,
+            } => {
+                if self.latent.set_samples(&latent, mode, samples) && self.view == View::Latent {
+                    self.synth
+                        .as_ref()
+                        .unwrap()
+                        .update_samples(self.latent.samples().unwrap().to_vec());
+                }
+            }
+            Response::DecodedVariant {
+                codes,
+                mode,
+                contribution,
+                codebook,
+                samples,
+            } => {
+                self.contributions
+                    .set_variant(&codes, mode, contribution, codebook, samples);
+            }
+            Response::Latent(latent) => {
+                self.latent.set_latent(latent);
+            }
+            Response::LatentStats(stats) => {
+                self.latent.set_stats(stats);
+            }
+            Response::Orderings(orderings) => {
+                self.editor.set_orderings(orderings);
+            }
+            Response::Encoded(codes) => {
+                self.incoming_codes = Some(codes);
+                self.error = None;
+            }
+            Response::Error(e) => {
+                self.error = Some(e);
+            }
+            Response::Loaded | Response::LoadFailed(_) => {}
+        }
+    }
+
+    /// Play the loop of the current view.
+    fn play_view(&mut self) {
+        self.contributions.stop_audition();
+        let samples = match self.view {
+            View::Editor => Some(self.samples.as_slice()),
+            View::Latent => self.latent.samples(),
+            // keeps playing until a candidate is auditioned
+            View::Evolution => None,
+        };
+        if let Some(samples) = samples {
+            self.synth
+                .as_ref()
+                .unwrap()
+                .update_samples(samples.to_vec());
+        }
+    }
+
+    fn draw_latent(&mut self, ui: &mut egui::Ui) {
+        let worker = self.worker.as_mut().unwrap();
+        let codes = self.codes.clone().unwrap_or_default();
+        draw_loop_mode(ui, &mut self.loop_mode);
+        latent::draw(ui, &mut self.latent, worker, &codes, self.loop_mode);
+    }
+
+    fn draw_evolution(&mut self, ui: &mut egui::Ui) {
+        let worker = self.worker.as_mut().unwrap();
+        let codes = self.codes.clone().unwrap_or_default();
+        let is_locked = |x, y| self.editor.is_locked(x, y);
+        match evolution::draw(
+            ui,
+            &mut self.evolution,
+            worker,
+            &codes,
+            self.loop_mode,
+            is_locked,
+        ) {
+            Some(evolution::Event::Audition(samples)) => {
+                self.synth.as_ref().unwrap().update_samples(samples);
+            }
+            Some(evolution::Event::Edit(codes)) => {
+                self.incoming_codes = Some(codes);
+                self.view = View::Editor;
+            }
+            None => {}
+        }
+    }
+
+    fn draw_editor(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let worker = self.worker.as_mut().unwrap();
+        draw_buffer(ui, &self.samples);
+        egui::CollapsingHeader::new("spectrogram").show(ui, |ui| {
+            spectrum_ui::draw(
+                ui,
+                &mut self.spectrogram,
+                &self.samples,
+                self.samples_generation,
+            );
+        });
+        egui::CollapsingHeader::new("codebook contributions").show(ui, |ui| {
+            let codes = self.codes.clone().unwrap_or_default();
+            match contribution_ui::draw(ui, &mut self.contributions, worker, &codes, self.loop_mode)
+            {
+                Some(contribution_ui::Event::Audition(samples)) => {
+                    self.synth.as_ref().unwrap().update_samples(samples);
+                }
+                Some(contribution_ui::Event::StopAudition) => {
+                    self.synth
+                        .as_ref()
+                        .unwrap()
+                        .update_samples(self.samples.clone());
+                }
+                None => {}
+            }
+        });
+        if ui
+            .add(
+                egui::Slider::new(&mut self.crossfade, 0.0..=1.0)
+                    .text("crossfade")
+                    .suffix(" s"),
+            )
+            .changed()
+        {
+            self.synth.as_ref().unwrap().set_crossfade(self.crossfade);
+        }
+        let loop_mode_changed = draw_loop_mode(ui, &mut self.loop_mode);
+        let (undo, redo) = ui
+            .horizontal(|ui| {
+                let undo = ui
+                    .add_enabled(self.history.can_undo(), egui::Button::new("⟲"))
+                    .on_hover_text("undo (ctrl+z)")
+                    .clicked();
+                let redo = ui
+                    .add_enabled(self.history.can_redo(), egui::Button::new("⟳"))
+                    .on_hover_text("redo (ctrl+shift+z or ctrl+y)")
+                    .clicked();
+                (undo, redo)
+            })
+            .inner;
+        // leave the shortcuts to text fields that have focus
+        let shortcuts = !ctx.wants_keyboard_input();
+        // check redo first since the undo shortcut also matches when shift is held
+        let redo = redo
+            || shortcuts
+                && ctx.input_mut(|i| {
+                    i.consume_shortcut(&REDO_SHORTCUT) || i.consume_shortcut(&REDO_SHORTCUT_ALT)
+                });
+        let undo = undo || shortcuts && ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT));
+        let mut new_codes = self.codes.clone().unwrap_or_default();
+        if let Some(codes) = self.incoming_codes.take() {
+            new_codes = codes;
+        }
+        code_ui::draw(ui, &mut new_codes, &mut self.editor);
+        ui.label("drop a wav file here to encode it using the current number of codebooks, or a saved patch to load it");
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            match open_dropped_file(worker, &file, new_codes.height()) {
+                Ok(Some(codes)) => {
+                    new_codes = codes;
+                    self.error = None;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let name = dropped_file_name(&file);
+                    warn!("unable to open {name:?}: {e:?}");
+                    self.error = Some(format!("unable to open {name}: {e:#}"));
+                }
+            }
+        }
+        if ui.button("copy link").clicked() {
+            match share::link(&new_codes) {
+                Ok(link) => ctx.copy_text(link),
+                Err(e) => {
+                    warn!("unable to create link: {e:?}");
+                    self.error = Some(format!("unable to create link: {e:#}"));
+                }
+            }
+        }
+        if draw_save(ui, &mut self.save) {
+            if let Err(e) = save(&self.save, &new_codes) {
+                warn!("unable to save: {e:?}");
+                self.error = Some(format!("unable to save: {e:#}"));
+            }
+        }
+        let device_sample_rate = self.audio.as_ref().and_then(|a| a.get_sample_rate());
+        let max_repeats = ((MAX_EXPORT_DURATION * compute::SAMPLE_RATE as f32) as usize
+            / self.samples.len().max(1))
+        .max(1);
+        if draw_export(ui, &mut self.export, device_sample_rate, max_repeats) {
+            if let Err(e) = export(&self.export, &self.samples, device_sample_rate) {
+                warn!("unable to export: {e:?}");
+                self.error = Some(format!("unable to export: {e:#}"));
+            }
+        }
+        let dragging = ctx.input(|i| i.pointer.any_down());
+        if !dragging {
+            self.history.end_drag();
+        }
+        if let Some(codes) = &self.codes {
+            if codes != &new_codes {
+                self.history.record(codes.clone(), dragging);
+            }
+        }
+        if redo {
+            if let Some(codes) = self.history.redo(new_codes.clone()) {
+                new_codes = codes;
+            }
+        } else if undo {
+            if let Some(codes) = self.history.undo(new_codes.clone()) {
+                new_codes = codes;
+            }
+        }
+        if Some(&new_codes) != self.codes.as_ref() {
+            share::write_codes(&new_codes);
+            worker.request(Request::Decode {
+                codes: new_codes.clone(),
+                mode: self.loop_mode,
+            });
+            self.codes = Some(new_codes);
+        } else if loop_mode_changed {
+            worker.request(Request::Decode {
+                codes: new_codes,
+                mode: self.loop_mode,
+            });
+        }
+    }
+}
+
 impl eframe::App for EncodecExplorer {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.label("encodec-explorer");
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
             match self.audio {
-                Some(_) => {
-                    self.compute = match std::mem::take(&mut self.compute) {
-                        ComputeState::Uninitialized => {
-                            ui.label("uninitialized");
-                            ComputeState::Loading(Promise::spawn_local(compute::Compute::new()))
-                        }
-                        ComputeState::Loading(p) => {
+                Some(ref mut audio) => {
+                    audio.update(ctx.input(|i| i.time));
+                    let worker = self.worker.get_or_insert_with(|| {
+                        let mut worker = Worker::spawn(ctx.clone());
+                        worker.request(Request::Orderings);
+                        worker.request(Request::LatentStats);
+                        worker
+                    });
+                    let responses: Vec<_> = std::iter::from_fn(|| worker.try_recv()).collect();
+                    let state = worker.state().clone();
+                    for response in responses {
+                        self.handle_response(response);
+                    }
+                    match state {
+                        worker::State::Loading => {
                             ui.add(Spinner::new());
-                            match p.try_take() {
-                                Ok(c) => ComputeState::Loaded(c.unwrap()),
-                                Err(p) => ComputeState::Loading(p),
-                            }
                         }
-                        ComputeState::Loaded(c) => {
+                        worker::State::Loaded => {
                             if ui.button("⏹").clicked() {
                                 self.audio = None;
                             } else {
-                                draw_buffer(ui, &self.samples);
-                                let mut new_codes = self.codes.clone().unwrap_or_default();
-                                code_ui::draw(ui, &mut new_codes);
-                                if Some(&new_codes) != self.codes.as_ref() {
-                                    self.codes = Some(new_codes);
-                                    // TODO: do the computation on a separate worker instead
-                                    self.samples = c
-                                        .decode_codes(
-                                            &self
-                                                .codes
-                                                .as_ref()
-                                                .unwrap()
-                                                .to_tensor(c.device())
-                                                .unwrap(),
-                                        )
-                                        .unwrap();
-                                    self.synth
-                                        .as_ref()
-                                        .unwrap()
-                                        .update_samples(self.samples.clone());
+                                audio_ui::draw(ui, self.audio.as_mut().unwrap());
+                                let view = self.view;
+                                ui.horizontal(|ui| {
+                                    ui.selectable_value(&mut self.view, View::Editor, "editor");
+                                    ui.selectable_value(
+                                        &mut self.view,
+                                        View::Evolution,
+                                        "evolution",
+                                    );
+                                    ui.selectable_value(&mut self.view, View::Latent, "latent");
+                                });
+                                match self.view {
+                                    View::Editor => self.draw_editor(ctx, ui),
+                                    View::Evolution => self.draw_evolution(ui),
+                                    View::Latent => self.draw_latent(ui),
+                                }
+                                if view != self.view {
+                                    self.evolution.stop_audition();
+                                    self.play_view();
                                 }
                             }
-                            ComputeState::Loaded(c)
                         }
-                    };
+                        worker::State::Failed(e) => {
+                            ui.colored_label(
+                                ui.visuals().error_fg_color,
+                                format!("unable to load model: {e}"),
+                            );
+                        }
+                    }
                 }
                 None => {
                     // need to wait with audio until a button is clicked
@@ -114,11 +460,141 @@ impl eframe::App for EncodecExplorer {
                 }
             }
         });
+        self.synth.as_ref().unwrap().update();
         // TODO: only repaint if something has happened
         ctx.request_repaint_after(Duration::from_secs(1));
     }
 }
 
+fn dropped_file_bytes(file: &egui::DroppedFile) -> anyhow::Result<Vec<u8>> {
+    if let Some(bytes) = &file.bytes {
+        Ok(bytes.to_vec())
+    } else if let Some(path) = &file.path {
+        Ok(std::fs::read(path)?)
+    } else {
+        anyhow::bail!("dropped file has no contents")
+    }
+}
+
+fn dropped_file_name(file: &egui::DroppedFile) -> String {
+    match &file.path {
+        Some(path) => path.to_string_lossy().into_owned(),
+        None => file.name.clone(),
+    }
+}
+
+/// Patches are loaded directly, wav files are sent to the worker to be encoded, keeping as many frames as fits in the editor.
+fn open_dropped_file(
+    worker: &mut Worker,
+    file: &egui::DroppedFile,
+    codebooks: usize,
+) -> anyhow::Result<Option<Codes>> {
+    let bytes = dropped_file_bytes(file)?;
+    if dropped_file_name(file).to_lowercase().ends_with(".wav") {
+        worker.request(Request::Encode {
+            samples: wav::read(&bytes)?,
+            codebooks,
+            max_frames: codes::MAX_FRAGMENTS,
+        });
+        Ok(None)
+    } else {
+        patch::load(&bytes).map(Some)
+    }
+}
+
+/// Returns true if the mode was changed.
+fn draw_loop_mode(ui: &mut egui::Ui, mode: &mut LoopMode) -> bool {
+    ui.horizontal(|ui| {
+        let before = *mode;
+        ui.label("loop decoding");
+        let warmup = match *mode {
+            LoopMode::WarmUp(frames) => frames,
+            LoopMode::Blend => DEFAULT_WARMUP_FRAMES,
+        };
+        ui.selectable_value(mode, LoopMode::WarmUp(warmup), "warm-up")
+            .on_hover_text("decode the loop once, after decoding the end of it");
+        ui.selectable_value(mode, LoopMode::Blend, "blend")
+            .on_hover_text("decode the loop twice and crossfade between them");
+        if let LoopMode::WarmUp(frames) = mode {
+            ui.add(
+                egui::DragValue::new(frames)
+                    .range(0..=MAX_WARMUP_FRAMES)
+                    .suffix(" frames"),
+            );
+        }
+        *mode != before
+    })
+    .inner
+}
+
+/// Returns true if the save button was clicked.
+fn draw_save(ui: &mut egui::Ui, settings: &mut SaveSettings) -> bool {
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut settings.file_name).desired_width(150.0));
+        ui.radio_value(&mut settings.format, patch::Format::Json, "json");
+        ui.radio_value(&mut settings.format, patch::Format::Binary, "binary");
+        ui.button("save").clicked()
+    })
+    .inner
+}
+
+fn save(settings: &SaveSettings, codes: &Codes) -> anyhow::Result<()> {
+    let data = patch::save(codes, settings.format)?;
+    files::save(
+        &format!("{}.{}", settings.file_name, settings.format.extension()),
+        settings.format.mime_type(),
+        &data,
+    )
+}
+
+/// Returns true if the export button was clicked.
+fn draw_export(
+    ui: &mut egui::Ui,
+    settings: &mut ExportSettings,
+    device_sample_rate: Option<u32>,
+    max_repeats: usize,
+) -> bool {
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut settings.file_name).desired_width(150.0));
+        ui.add(
+            egui::DragValue::new(&mut settings.repeats)
+                .range(1..=max_repeats)
+                .suffix(" repeats"),
+        );
+        ui.add_enabled(
+            device_sample_rate.is_some(),
+            egui::Checkbox::new(&mut settings.device_sample_rate, "device sample rate"),
+        );
+        ui.radio_value(&mut settings.format, wav::Format::Int16, "16-bit");
+        ui.radio_value(&mut settings.format, wav::Format::Float32, "float");
+        ui.button("export").clicked()
+    })
+    .inner
+}
+
+fn export(
+    settings: &ExportSettings,
+    samples: &[f32],
+    device_sample_rate: Option<u32>,
+) -> anyhow::Result<()> {
+    let duration = (samples.len() * settings.repeats) as f32 / compute::SAMPLE_RATE as f32;
+    anyhow::ensure!(
+        duration <= MAX_EXPORT_DURATION,
+        "the export would be {duration:.0} seconds, at most {MAX_EXPORT_DURATION} are allowed"
+    );
+    // resample a single period, so it still loops seamlessly
+    let (samples, sample_rate) = match device_sample_rate {
+        Some(rate) if settings.device_sample_rate => (
+            resample::resample(samples, compute::SAMPLE_RATE, rate, true),
+            rate,
+        ),
+        _ => (samples.to_vec(), compute::SAMPLE_RATE),
+    };
+    let samples = samples.repeat(settings.repeats);
+    let data = wav::write(&samples, sample_rate, settings.format)?;
+    files::save(&settings.file_name, "audio/wav", &data)
+}
+
 fn draw_buffer(ui: &mut egui::Ui, buffer: &[f32]) {
     let plot_width = ui.available_width().min((250 * buffer.len() / 320) as f32);
     let (_, rect) = ui.allocate_space(vec2(plot_width, 150.0));
@@ -128,10 +604,13 @@ fn draw_buffer(ui: &mut egui::Ui, buffer: &[f32]) {
         Rect::from_x_y_ranges(0.0..=(buffer.len() - 1) as f32, -1.0..=1.0),
         rect,
     );
+    // a few points per pixel is enough
+    let step = (buffer.len() / (4 * plot_width.max(1.0) as usize)).max(1);
     let line: Vec<Pos2> = buffer
         .iter()
         .copied()
         .enumerate()
+        .step_by(step)
         .map(|(x, y)| to_rect * pos2(x as f32, y))
         .collect();
     p.add(epaint::Shape::line(line, Stroke::new(1f32, Color32::GRAY)));
@@ -3,23 +3,116 @@ use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BufferSize, Device, OutputCallbackInfo, SampleFormat, Stream, SupportedBufferSize,
-    SupportedStreamConfigRange,
+    BufferSize, Device, FromSample, HostId, OutputCallbackInfo, SampleFormat, SampleRate,
+    SizedSample, Stream, StreamConfig, SupportedBufferSize, SupportedStreamConfigRange,
 };
 use crossbeam::atomic::AtomicCell;
-use log::warn;
+use log::{info, warn};
 
 pub trait Synth {
     fn play(&self, sample_rate: u32, channels: usize, out_samples: &mut [f32]);
 }
 
+/// Preference of sample formats, higher is better. `None` for unsupported formats.
+fn sample_format_rank(format: SampleFormat) -> Option<u8> {
+    Some(match format {
+        SampleFormat::F32 => 9,
+        SampleFormat::F64 => 8,
+        SampleFormat::I32 => 7,
+        SampleFormat::I16 => 6,
+        SampleFormat::U32 => 5,
+        SampleFormat::U16 => 4,
+        SampleFormat::I64 => 3,
+        SampleFormat::U64 => 2,
+        SampleFormat::I8 => 1,
+        SampleFormat::U8 => 0,
+        _ => return None,
+    })
+}
+
+/// Prefer configs that support the default sample rate, then stereo, then mono, then better sample formats.
+fn config_rank(
+    config: &SupportedStreamConfigRange,
+    default_sample_rate: SampleRate,
+) -> (bool, u8, u8) {
+    let supports_default_rate =
+        (config.min_sample_rate()..=config.max_sample_rate()).contains(&default_sample_rate);
+    let channels_rank = match config.channels() {
+        2 => 2,
+        1 => 1,
+        _ => 0,
+    };
+    (
+        supports_default_rate,
+        channels_rank,
+        sample_format_rank(config.sample_format()).unwrap_or(0),
+    )
+}
+
+/// Everything the stream callbacks need, for building a stream of any sample format.
+struct StreamCallbacks {
+    sample_rate: u32,
+    synth: Arc<dyn Synth + Send + Sync>,
+    error_callback: Arc<Box<dyn Fn(String) + Send + Sync>>,
+    stream_failed: Arc<AtomicCell<bool>>,
+    buffer_size: Arc<AtomicCell<u32>>,
+}
+
+impl StreamCallbacks {
+    fn build<T>(self, device: &Device, config: &StreamConfig) -> Result<Stream>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let Self {
+            sample_rate,
+            synth,
+            error_callback,
+            stream_failed,
+            buffer_size,
+        } = self;
+        let channels = config.channels.into();
+        // the synth renders f32, which is then converted to the device format
+        let mut scratch: Vec<f32> = Vec::new();
+        Ok(device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &OutputCallbackInfo| {
+                buffer_size.store((data.len() / channels) as u32);
+                // only allocates if the buffer size grows
+                scratch.resize(data.len(), 0.0);
+                synth.play(sample_rate, channels, &mut scratch);
+                for (out, &s) in data.iter_mut().zip(&scratch) {
+                    *out = T::from_sample(s);
+                }
+            },
+            move |error| {
+                stream_failed.store(true);
+                error_callback(format!("error: {:?}", error));
+            },
+            // no timeout
+            None,
+        )?)
+    }
+}
+
 pub struct AudioManager {
+    host_id: HostId,
+    /// Output device to use, or the default one if `None`.
+    device_name: Option<String>,
+    device_names: Vec<String>,
     device: Option<Device>,
     config_range: Option<SupportedStreamConfigRange>,
+    sample_rate: Option<u32>,
     buffer_size: Arc<AtomicCell<u32>>,
+    forced_sample_rate: Option<u32>,
     forced_buffer_size: Option<u32>,
     stream: Option<Stream>,
     error_callback: Arc<Box<dyn Fn(String) + Send + Sync>>,
+    /// Set by the stream error callback, so that the stream gets rebuilt on the next update.
+    stream_failed: Arc<AtomicCell<bool>>,
+    /// The last error that stopped the stream, if it isn't running.
+    error: Option<String>,
+    /// Time of the next check for device changes, in the same unit as passed to [`Self::update`].
+    next_check: f64,
     synth: Arc<dyn Synth + Send + Sync>,
 }
 
@@ -29,45 +122,62 @@ impl AudioManager {
         U: Fn(String) + Send + Sync + 'static,
     {
         let mut s = Self {
+            host_id: cpal::default_host().id(),
+            device_name: None,
+            device_names: Vec::new(),
             device: None,
             config_range: None,
+            sample_rate: None,
             buffer_size: Arc::new(AtomicCell::new(0)),
+            forced_sample_rate: None,
             forced_buffer_size: None,
             stream: None,
             error_callback: Arc::new(Box::new(error_callback)),
+            stream_failed: Arc::new(AtomicCell::new(false)),
+            error: None,
+            next_check: 0.0,
             synth,
         };
+        s.refresh_devices();
         s.setup();
         s
     }
 
     fn setup(&mut self) {
         self.stream = None;
+        self.sample_rate = None;
+        self.error = None;
+        self.stream_failed.store(false);
         let r = (|| -> Result<_> {
             if self.device.is_none() {
-                let host = cpal::default_host();
-                self.device = host.default_output_device();
+                let host = cpal::host_from_id(self.host_id)?;
+                self.device = match &self.device_name {
+                    Some(name) => host
+                        .output_devices()?
+                        .find(|device| device.name().ok().as_ref() == Some(name)),
+                    None => host.default_output_device(),
+                };
                 self.config_range = None;
             }
             if let Some(ref device) = self.device {
+                let default_sample_rate = device.default_output_config()?.sample_rate();
                 if self.config_range.is_none() {
                     self.config_range = Some(
                         device
                             .supported_output_configs()?
-                            // just pick the first valid config
-                            .find(|config| {
-                                // only stereo configs
-                                config.sample_format() == SampleFormat::F32
-                                    && config.channels() == 2
-                            })
+                            .filter(|config| sample_format_rank(config.sample_format()).is_some())
+                            .max_by_key(|config| config_rank(config, default_sample_rate))
                             .ok_or_else(|| anyhow!("no valid output audio config found"))?,
                     );
                 }
                 if let Some(ref supported_config) = self.config_range {
-                    let sample_rate = device.default_output_config()?.sample_rate().clamp(
-                        supported_config.min_sample_rate(),
-                        supported_config.max_sample_rate(),
-                    );
+                    let sample_rate = self
+                        .forced_sample_rate
+                        .map_or(default_sample_rate, SampleRate)
+                        .clamp(
+                            supported_config.min_sample_rate(),
+                            supported_config.max_sample_rate(),
+                        );
                     let mut config = supported_config.with_sample_rate(sample_rate).config();
                     if let SupportedBufferSize::Range { min, max } = supported_config.buffer_size()
                     {
@@ -80,37 +190,157 @@ impl AudioManager {
                             }
                         }
                     }
+                    let callbacks = StreamCallbacks {
+                        sample_rate: sample_rate.0,
+                        synth: self.synth.clone(),
+                        error_callback: self.error_callback.clone(),
+                        stream_failed: self.stream_failed.clone(),
+                        buffer_size: self.buffer_size.clone(),
+                    };
+                    let stream = match supported_config.sample_format() {
+                        SampleFormat::F32 => callbacks.build::<f32>(device, &config),
+                        SampleFormat::F64 => callbacks.build::<f64>(device, &config),
+                        SampleFormat::I8 => callbacks.build::<i8>(device, &config),
+                        SampleFormat::I16 => callbacks.build::<i16>(device, &config),
+                        SampleFormat::I32 => callbacks.build::<i32>(device, &config),
+                        SampleFormat::I64 => callbacks.build::<i64>(device, &config),
+                        SampleFormat::U8 => callbacks.build::<u8>(device, &config),
+                        SampleFormat::U16 => callbacks.build::<u16>(device, &config),
+                        SampleFormat::U32 => callbacks.build::<u32>(device, &config),
+                        SampleFormat::U64 => callbacks.build::<u64>(device, &config),
+                        format => Err(anyhow!("unsupported sample format {format}")),
+                    }?;
                     let sample_rate = sample_rate.0;
-                    let channels = config.channels.into();
-                    let synth = self.synth.clone();
-                    let error_callback = self.error_callback.clone();
-                    let buffer_size = self.buffer_size.clone();
-                    let stream = device.build_output_stream(
-                        &config,
-                        move |data: &mut [f32], _: &OutputCallbackInfo| {
-                            buffer_size.store((data.len() / channels) as u32);
-                            synth.play(sample_rate, channels, data);
-                        },
-                        move |error| {
-                            error_callback(format!("error: {:?}", error));
-                        },
-                        // no timeout
-                        None,
-                    )?;
                     stream.play()?;
                     self.stream = Some(stream);
+                    self.sample_rate = Some(sample_rate);
                 }
             } else {
                 warn!("no output device found");
+                self.error = Some("no output device found".to_string());
             }
             Ok(())
         })();
         if let Err(e) = r {
+            self.error = Some(format!("{e}"));
             (self.error_callback)(format!("error: {:?}", e));
         }
     }
 
+    /// Rebuild the stream if it has failed or the default device has changed.
+    /// Should be called regularly, `time` is in seconds.
+    pub fn update(&mut self, time: f64) {
+        const CHECK_INTERVAL: f64 = 2.0;
+        let mut rebuild = false;
+        if self.stream_failed.swap(false) {
+            warn!("audio stream failed, restarting");
+            self.error = Some("audio stream failed".to_string());
+            self.stream = None;
+            rebuild = true;
+        }
+        if time >= self.next_check {
+            self.next_check = time + CHECK_INTERVAL;
+            if self.stream.is_none() {
+                // retry, the device might be back
+                rebuild = true;
+            } else if self.device_name.is_none() {
+                let default_name = cpal::host_from_id(self.host_id)
+                    .ok()
+                    .and_then(|host| host.default_output_device())
+                    .and_then(|device| device.name().ok());
+                if default_name != self.get_name() {
+                    info!("default output device changed to {default_name:?}");
+                    rebuild = true;
+                }
+            }
+        }
+        if rebuild {
+            self.device = None;
+            self.refresh_devices();
+            self.setup();
+        }
+    }
+
+    /// Why the stream isn't running, if it isn't.
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
     pub fn get_name(&self) -> Option<String> {
         self.device.as_ref()?.name().ok()
     }
+
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// The buffer size seen in the last audio callback, in frames.
+    pub fn get_buffer_size(&self) -> u32 {
+        self.buffer_size.load()
+    }
+
+    pub fn get_host_id(&self) -> HostId {
+        self.host_id
+    }
+
+    /// `None` if the default device is used.
+    pub fn get_device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Names of the output devices of the current host, as of the last [`Self::refresh_devices`].
+    pub fn get_device_names(&self) -> &[String] {
+        &self.device_names
+    }
+
+    pub fn get_config_range(&self) -> Option<&SupportedStreamConfigRange> {
+        self.config_range.as_ref()
+    }
+
+    pub fn get_forced_sample_rate(&self) -> Option<u32> {
+        self.forced_sample_rate
+    }
+
+    pub fn get_forced_buffer_size(&self) -> Option<u32> {
+        self.forced_buffer_size
+    }
+
+    pub fn refresh_devices(&mut self) {
+        let r = (|| -> Result<_> {
+            Ok(cpal::host_from_id(self.host_id)?
+                .output_devices()?
+                .filter_map(|device| device.name().ok())
+                .collect())
+        })();
+        match r {
+            Ok(names) => self.device_names = names,
+            Err(e) => (self.error_callback)(format!("error: {:?}", e)),
+        }
+    }
+
+    pub fn set_host(&mut self, host_id: HostId) {
+        self.host_id = host_id;
+        self.device_name = None;
+        self.device = None;
+        self.refresh_devices();
+        self.setup();
+    }
+
+    pub fn set_device(&mut self, name: Option<String>) {
+        self.device_name = name;
+        self.device = None;
+        self.setup();
+    }
+
+    /// `None` to use the default sample rate of the device.
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.forced_sample_rate = sample_rate;
+        self.setup();
+    }
+
+    /// `None` to use the default buffer size of the device.
+    pub fn set_buffer_size(&mut self, buffer_size: Option<u32>) {
+        self.forced_buffer_size = buffer_size;
+        self.setup();
+    }
 }
@@ -0,0 +1,129 @@
+use cpal::SupportedBufferSize;
+
+use crate::audio::AudioManager;
+
+pub fn draw(ui: &mut egui::Ui, audio: &mut AudioManager) {
+    match audio.get_error() {
+        Some(error) => {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("audio stopped, retrying: {error}"),
+            );
+        }
+        None => {
+            ui.label(format!(
+                "audio device: {}",
+                audio.get_name().unwrap_or_default()
+            ));
+        }
+    }
+    ui.collapsing("audio settings", |ui| {
+        egui::Grid::new("audio_settings").show(ui, |ui| {
+            ui.label("host");
+            let mut host_id = audio.get_host_id();
+            egui::ComboBox::from_id_salt("host")
+                .selected_text(host_id.name())
+                .show_ui(ui, |ui| {
+                    for id in cpal::available_hosts() {
+                        ui.selectable_value(&mut host_id, id, id.name());
+                    }
+                });
+            if host_id != audio.get_host_id() {
+                audio.set_host(host_id);
+            }
+            ui.end_row();
+
+            ui.label("device");
+            let mut device_name = audio.get_device_name().map(str::to_string);
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("device")
+                    .selected_text(device_name.as_deref().unwrap_or("default"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut device_name, None, "default");
+                        for name in audio.get_device_names() {
+                            ui.selectable_value(&mut device_name, Some(name.clone()), name);
+                        }
+                    });
+                if ui.button("⟳").on_hover_text("refresh devices").clicked() {
+                    audio.refresh_devices();
+                }
+            });
+            if device_name.as_deref() != audio.get_device_name() {
+                audio.set_device(device_name);
+            }
+            ui.end_row();
+
+            let Some(range) = audio.get_config_range().cloned() else {
+                return;
+            };
+
+            ui.label("sample rate");
+            let mut forced = audio.get_forced_sample_rate();
+            draw_optional_value(
+                ui,
+                "sample_rate",
+                &mut forced,
+                audio.get_sample_rate().unwrap_or(range.min_sample_rate().0),
+                range.min_sample_rate().0..=range.max_sample_rate().0,
+                " Hz",
+            );
+            if forced != audio.get_forced_sample_rate() {
+                audio.set_sample_rate(forced);
+            }
+            ui.end_row();
+
+            ui.label("buffer size");
+            ui.horizontal(|ui| {
+                if let SupportedBufferSize::Range { min, max } = *range.buffer_size() {
+                    let mut forced = audio.get_forced_buffer_size();
+                    draw_optional_value(
+                        ui,
+                        "buffer_size",
+                        &mut forced,
+                        audio.get_buffer_size().clamp(min, max),
+                        min..=max,
+                        " frames",
+                    );
+                    if forced != audio.get_forced_buffer_size() {
+                        audio.set_buffer_size(forced);
+                    }
+                }
+                ui.label(format!("measured: {}", audio.get_buffer_size()));
+            });
+            ui.end_row();
+        });
+    });
+}
+
+/// A value that is either the device default or set by the user.
+fn draw_optional_value(
+    ui: &mut egui::Ui,
+    id_salt: &str,
+    value: &mut Option<u32>,
+    default: u32,
+    range: std::ops::RangeInclusive<u32>,
+    suffix: &str,
+) {
+    ui.horizontal(|ui| {
+        let mut is_default = value.is_none();
+        if ui.checkbox(&mut is_default, "default").changed() {
+            *value = if is_default { None } else { Some(default) };
+        }
+        // only apply when done editing, since every change rebuilds the stream
+        let id = ui.make_persistent_id(id_salt);
+        let mut v = ui
+            .data(|d| d.get_temp::<u32>(id))
+            .unwrap_or(value.unwrap_or(default));
+        let response = ui.add_enabled(
+            value.is_some(),
+            egui::DragValue::new(&mut v).range(range).suffix(suffix),
+        );
+        if response.changed() {
+            ui.data_mut(|d| d.insert_temp(id, v));
+        }
+        if value.is_some() && (response.drag_stopped() || response.lost_focus()) {
+            *value = Some(v);
+            ui.data_mut(|d| d.remove::<u32>(id));
+        }
+    });
+}
@@ -0,0 +1,12 @@
+//! Web worker that runs the model for the web build. See `worker.rs`.
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    eframe::WebLogger::init(log::LevelFilter::Debug).ok();
+    encodec_explorer::web_worker_main();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("decode-worker is only used by the web build");
+}
@@ -0,0 +1,64 @@
+//! Renders saved patches or inline codes to wav files, without the gui.
+
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "\
+usage: encodec-explorer-render [options] <patch>
+       encodec-explorer-render [options] --codes <codes>
+
+Decodes the codes into a loop, the same way as the app does, and writes it to a wav file.
+
+options:
+  -c, --codes <codes>       codebook rows separated by ';' with comma separated codes, e.g. \"1,2,3;4,5,6\",
+                            or a link copied from the app
+  -o, --output <file>       wav file to write [default: out.wav]
+  -d, --duration <seconds>  length of the output, at most 600, looping the codes [default: one loop]
+  -r, --sample-rate <rate>  sample rate of the output, at most 192000 [default: 24000]
+  -w, --warmup <frames>     frames to warm up the decoder with when looping, at most 128 [default: 16]
+      --blend               loop by blending two decoded periods instead of warming up
+      --float               write 32 bit float samples instead of 16 bit integers
+  -h, --help                print this help
+
+The model is downloaded from huggingface, unless ENCODEC_MODEL_PATH is set.";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    use encodec_explorer::render::{self, Input, Options};
+
+    env_logger::init();
+    let mut options = Options::default();
+    let mut input = None;
+    let mut output = std::path::PathBuf::from("out.wav");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("missing value for {arg}\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            "-c" | "--codes" => input = Some(Input::Inline(value()?)),
+            "-o" | "--output" => output = value()?.into(),
+            "-d" | "--duration" => options.duration = Some(value()?.parse()?),
+            "-r" | "--sample-rate" => options.sample_rate = value()?.parse()?,
+            "-w" | "--warmup" => options.warmup = Some(value()?.parse()?),
+            "--blend" => options.warmup = None,
+            "--float" => options.float = true,
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option {arg}\n\n{USAGE}"),
+            path => {
+                let data = std::fs::read(path).with_context(|| format!("unable to read {path}"))?;
+                input = Some(Input::Patch(data));
+            }
+        }
+    }
+    let input = input.with_context(|| format!("no patch or codes given\n\n{USAGE}"))?;
+    let data = render::render(input, &options)?;
+    std::fs::write(&output, data).with_context(|| format!("unable to write {output:?}"))?;
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -1,154 +1,653 @@
-use candle_core::{Device, Tensor};
-use egui::Slider;
-use num::Integer as _;
+use std::{collections::HashSet, ops::Range};
 
-// TODO: use some existing type for 2d arrays instead?
-#[derive(Clone)]
-pub struct Codes {
+use egui::{vec2, Color32, Pos2, Rect, Sense, Slider, Stroke};
+
+use crate::{
+    codes::{next_rng, Codes, BANDWIDTHS, MAX_CODE, MAX_FRAGMENTS, MAX_LAYERS},
+    compute,
+};
+
+/// Width of each frame column in the editor.
+const COLUMN_WIDTH: f32 = 240.0;
+/// Size of each cell of the heatmap.
+const CELL_SIZE: f32 = 14.0;
+/// Scrolling that steps a code by one, in points. About one notch of a mouse wheel.
+const SCROLL_STEP: f32 = 50.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Layout {
+    Sliders,
+    Heatmap,
+}
+
+/// What dragging over the heatmap does.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Tool {
+    Paint,
+    Select,
+}
+
+/// Codes of a codebook ordered by similarity.
+struct Ordering {
     codes: Vec<u32>,
-    width: usize,
+    /// Position of each code in `codes`.
+    positions: Vec<u32>,
 }
 
-impl Default for Codes {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Editor state that isn't part of the codes.
+pub struct EditorState {
+    /// Per codebook orderings of the codes, once computed by the worker.
+    orderings: Vec<Ordering>,
+    /// Whether the sliders move through the codes in order of similarity rather than by index.
+    ordered: bool,
+    /// `(frame, codebook)` of the cells that are left alone by randomization and mutation.
+    locked: HashSet<(usize, usize)>,
+    /// Seed of the next random operation, see [`next_rng`].
+    seed: u64,
+    row: usize,
+    column: usize,
+    mutation_amount: u32,
+    mutation_rate: f32,
+    layout: Layout,
+    tool: Tool,
+    /// Code set by painting and by entering it for the selection.
+    paint: u32,
+    /// Last painted cell while dragging, so fast strokes don't leave gaps.
+    last_painted: Option<(usize, usize)>,
+    /// Scrolling over the heatmap that hasn't added up to a step yet, in points.
+    scroll: f32,
+    /// `(frame, codebook)` of the selected cells of the heatmap.
+    selection: HashSet<(usize, usize)>,
+    /// Corners of the rectangle being selected.
+    selecting: Option<((usize, usize), (usize, usize))>,
 }
 
-impl Codes {
-    pub fn new() -> Self {
+impl Default for EditorState {
+    fn default() -> Self {
         Self {
-            codes: vec![0],
-            width: 1,
+            orderings: Vec::new(),
+            ordered: false,
+            locked: HashSet::new(),
+            seed: 0,
+            row: 0,
+            column: 0,
+            mutation_amount: 16,
+            mutation_rate: 0.25,
+            layout: Layout::Sliders,
+            tool: Tool::Paint,
+            paint: 0,
+            last_painted: None,
+            scroll: 0.0,
+            selection: HashSet::new(),
+            selecting: None,
         }
     }
+}
 
-    pub fn to_tensor(&self, device: &Device) -> anyhow::Result<Tensor> {
-        Ok(if self.width == 0 {
-            Tensor::zeros((0, 0), candle_core::DType::U32, device)?
-        } else {
-            debug_assert!(self.codes.len() % self.width == 0);
-            let height = self.codes.len() / self.width;
-            Tensor::from_vec(self.codes.clone(), (height, self.width), device)?
-        })
+impl EditorState {
+    /// Set the codes of each codebook ordered by similarity.
+    pub fn set_orderings(&mut self, orderings: Vec<Vec<u32>>) {
+        self.orderings = orderings
+            .into_iter()
+            .map(|codes| {
+                let mut positions = vec![0; codes.len()];
+                for (i, &code) in codes.iter().enumerate() {
+                    positions[code as usize] = i as u32;
+                }
+                Ordering { codes, positions }
+            })
+            .collect();
     }
 
-    fn height(&self) -> usize {
-        debug_assert!(self.width > 0);
-        debug_assert!(!self.codes.is_empty());
-        if self.width == 0 {
-            0
-        } else {
-            self.codes.len() / self.width
-        }
+    pub fn is_locked(&self, x: usize, y: usize) -> bool {
+        self.locked.contains(&(x, y))
     }
+}
 
-    fn get(&self, x: usize, y: usize) -> Option<u32> {
-        if x < self.width && y < self.height() {
-            Some(self.codes[y * self.width + x])
-        } else {
-            None
+/// Ordering of codebook `y`, if the codes should be ordered by similarity.
+fn ordering(orderings: &[Ordering], ordered: bool, y: usize) -> Option<&Ordering> {
+    orderings
+        .get(y)
+        .filter(|o| ordered && o.codes.len() == MAX_CODE as usize + 1)
+}
+
+fn draw_random(ui: &mut egui::Ui, codes: &mut Codes, state: &mut EditorState) {
+    ui.horizontal(|ui| {
+        ui.label("seed");
+        ui.add(egui::DragValue::new(&mut state.seed));
+        if ui
+            .button("🎲")
+            .on_hover_text("pick a random seed")
+            .clicked()
+        {
+            state.seed = rand::random();
         }
-    }
+        ui.separator();
+        ui.label("randomize");
+        if ui.button("all").clicked() {
+            let mut rng = next_rng(&mut state.seed);
+            codes.randomize(&mut rng, |x, y| !state.is_locked(x, y));
+        }
+        state.row = state.row.min(codes.height() - 1);
+        if ui.button("row").clicked() {
+            let mut rng = next_rng(&mut state.seed);
+            codes.randomize(&mut rng, |x, y| y == state.row && !state.is_locked(x, y));
+        }
+        ui.add(egui::DragValue::new(&mut state.row).range(0..=codes.height() - 1));
+        state.column = state.column.min(codes.width() - 1);
+        if ui.button("column").clicked() {
+            let mut rng = next_rng(&mut state.seed);
+            codes.randomize(&mut rng, |x, y| x == state.column && !state.is_locked(x, y));
+        }
+        ui.add(egui::DragValue::new(&mut state.column).range(0..=codes.width() - 1));
+        ui.separator();
+        if ui.button("mutate").clicked() {
+            let mut rng = next_rng(&mut state.seed);
+            codes.mutate(
+                &mut rng,
+                state.mutation_amount,
+                state.mutation_rate,
+                |x, y| !state.is_locked(x, y),
+            );
+        }
+        ui.add(
+            egui::DragValue::new(&mut state.mutation_amount)
+                .range(1..=MAX_CODE)
+                .prefix("±"),
+        )
+        .on_hover_text("largest change of a code");
+        ui.add(
+            egui::DragValue::new(&mut state.mutation_rate)
+                .range(0.0..=1.0)
+                .speed(0.01)
+                .prefix("p "),
+        )
+        .on_hover_text("probability of each code changing");
+        ui.separator();
+        if ui
+            .add_enabled(!state.locked.is_empty(), egui::Button::new("unlock all"))
+            .clicked()
+        {
+            state.locked.clear();
+        }
+        ui.separator();
+        ui.add_enabled(
+            !state.orderings.is_empty(),
+            egui::Checkbox::new(&mut state.ordered, "order by similarity"),
+        )
+        .on_hover_text("move the sliders through the codes in order of how similar their embeddings are, rather than by index");
+    });
+}
 
-    fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut u32> {
-        if x < self.width && y < self.height() {
-            Some(&mut self.codes[y * self.width + x])
-        } else {
-            None
+/// The columns of `column_width` that `viewport` shows some of, out of `columns`.
+/// There can be hundreds of columns, so only these are drawn.
+fn visible_columns(viewport: Rect, column_width: f32, columns: usize) -> Range<usize> {
+    let first = ((viewport.min.x / column_width).floor().max(0.0) as usize).min(columns);
+    let last = ((viewport.max.x / column_width).ceil().max(0.0) as usize).min(columns);
+    first..last
+}
+
+/// Draw `columns` columns of `rows` widgets each in a scroll area, only calling `add_column` for the visible ones.
+pub fn draw_columns(
+    ui: &mut egui::Ui,
+    columns: usize,
+    rows: usize,
+    column_width: f32,
+    mut add_column: impl FnMut(&mut egui::Ui, usize),
+) {
+    egui::ScrollArea::both()
+        .max_height(500.0)
+        .auto_shrink([false, true])
+        .show_viewport(ui, |ui, viewport| {
+            let row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+            let height = row_height * (rows + 1) as f32;
+            ui.set_width(column_width * columns as f32);
+            ui.set_height(height);
+            let top = ui.max_rect().top();
+            for x in visible_columns(viewport, column_width, columns) {
+                let left = ui.max_rect().left() + x as f32 * column_width;
+                if x > 0 {
+                    ui.painter().vline(
+                        left,
+                        top..=top + height,
+                        ui.visuals().widgets.noninteractive.bg_stroke,
+                    );
+                }
+                let rect = egui::Rect::from_x_y_ranges(
+                    left + ui.spacing().item_spacing.x..=left + column_width,
+                    top..=top + height,
+                );
+                ui.allocate_new_ui(egui::UiBuilder::new().max_rect(rect).id_salt(x), |ui| {
+                    ui.weak(format!("{x}"));
+                    add_column(ui, x);
+                });
+            }
+        });
+}
+
+fn draw_sliders(ui: &mut egui::Ui, codes: &mut Codes, state: &mut EditorState) {
+    let (width, height) = (codes.width(), codes.height());
+    draw_columns(ui, width, height, COLUMN_WIDTH, |ui, x| {
+        for y in 0..codes.height() {
+            let value = codes.get_mut(x, y).unwrap();
+            let ordering = ordering(&state.orderings, state.ordered, y);
+            ui.horizontal(|ui| {
+                // the slider and buttons move through the positions of the ordering, while showing codes
+                let mut position = match ordering {
+                    Some(ordering) => ordering.positions[*value as usize],
+                    None => *value,
+                };
+                let mut slider = Slider::new(&mut position, 0..=MAX_CODE);
+                if let Some(ordering) = ordering {
+                    slider = slider
+                        .custom_formatter(|p, _| ordering.codes[p as usize].to_string())
+                        .custom_parser(|text| {
+                            let code = text.parse::<usize>().ok()?;
+                            Some(*ordering.positions.get(code)? as f64)
+                        });
+                }
+                ui.add(slider);
+                if ui
+                    .add_enabled(position > 0, egui::Button::new("-").small())
+                    .clicked()
+                {
+                    position -= 1;
+                }
+                if ui
+                    .add_enabled(position < MAX_CODE, egui::Button::new("+").small())
+                    .clicked()
+                {
+                    position += 1;
+                };
+                *value = match ordering {
+                    Some(ordering) => ordering.codes[position as usize],
+                    None => position,
+                };
+                let locked = state.locked.contains(&(x, y));
+                if ui
+                    .selectable_label(locked, if locked { "🔒" } else { "🔓" })
+                    .on_hover_text("locked codes are kept when randomizing")
+                    .clicked()
+                {
+                    if locked {
+                        state.locked.remove(&(x, y));
+                    } else {
+                        state.locked.insert((x, y));
+                    }
+                }
+            });
         }
-    }
+    });
+}
 
-    fn reshape(&mut self, width: usize, height: usize) {
-        assert!(width >= 1);
-        assert!(height >= 1);
-        self.codes = (0..height * width)
-            .map(|i| {
-                let (y, x) = i.div_rem(&width);
-                self.get(x, y).unwrap_or(0)
-            })
-            .collect();
-        self.width = width;
-    }
+/// Color of a cell, by the position of its code in the ordering so that similar codes get similar colors.
+fn cell_color(position: u32) -> Color32 {
+    let t = position as f32 / (MAX_CODE + 1) as f32;
+    egui::ecolor::Hsva::new(t, 0.75, 0.9, 1.0).into()
 }
 
-impl PartialEq for Codes {
-    fn eq(&self, other: &Self) -> bool {
-        self.codes == other.codes && self.width == other.width
-    }
+/// Cells on the line from `a` to `b`.
+fn line_cells(a: (usize, usize), b: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+    let (dx, dy) = (b.0 as f32 - a.0 as f32, b.1 as f32 - a.1 as f32);
+    let steps = dx.abs().max(dy.abs()) as usize;
+    (0..=steps).map(move |i| {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            i as f32 / steps as f32
+        };
+        (
+            (a.0 as f32 + dx * t).round() as usize,
+            (a.1 as f32 + dy * t).round() as usize,
+        )
+    })
 }
 
-pub fn draw(ui: &mut egui::Ui, codes: &mut Codes) {
-    const MAX_FRAGMENTS: usize = 4;
-    const MAX_LAYERS: usize = 32;
-    ui.group(|ui| {
-        egui::ScrollArea::vertical()
-            .max_height(500.0)
-            .show(ui, |ui| {
-                // TODO: use a table?
-                ui.vertical_centered(|ui| {
-                    ui.horizontal(|ui| {
-                        if ui
-                            .add_enabled(codes.width > 1, egui::Button::new("⬅").small())
-                            .clicked()
-                        {
-                            codes.reshape(codes.width - 1, codes.height());
+/// Cells of the rectangle with corners `a` and `b`.
+fn rect_cells(a: (usize, usize), b: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+    (a.0.min(b.0)..=a.0.max(b.0))
+        .flat_map(move |x| (a.1.min(b.1)..=a.1.max(b.1)).map(move |y| (x, y)))
+}
+
+fn draw_heatmap_tools(ui: &mut egui::Ui, codes: &mut Codes, state: &mut EditorState) {
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut state.tool, Tool::Paint, "paint")
+            .on_hover_text("drag to set cells to the code, right click a cell to pick its code");
+        ui.radio_value(&mut state.tool, Tool::Select, "select")
+            .on_hover_text("drag to select a rectangle, hold shift to add to the selection");
+        let entry = ui
+            .add(
+                egui::DragValue::new(&mut state.paint)
+                    .range(0..=MAX_CODE)
+                    .prefix("code "),
+            )
+            .on_hover_text("press enter after typing a code to set the selected cells to it");
+        let entered = entry.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        ui.separator();
+        ui.add_enabled_ui(!state.selection.is_empty(), |ui| {
+            if ui.button("set selection").clicked() || entered {
+                for &(x, y) in &state.selection {
+                    if state.locked.contains(&(x, y)) {
+                        continue;
+                    }
+                    if let Some(value) = codes.get_mut(x, y) {
+                        *value = state.paint;
+                    }
+                }
+            }
+            if ui.button("lock selection").clicked() {
+                state.locked.extend(state.selection.iter().copied());
+            }
+            if ui.button("unlock selection").clicked() {
+                for cell in &state.selection {
+                    state.locked.remove(cell);
+                }
+            }
+            if ui.button("clear selection").clicked() {
+                state.selection.clear();
+            }
+        });
+    });
+}
+
+/// A compact alternative to the sliders, one colored cell per code.
+fn draw_heatmap(ui: &mut egui::Ui, codes: &mut Codes, state: &mut EditorState) {
+    draw_heatmap_tools(ui, codes, state);
+    let (width, height) = (codes.width(), codes.height());
+    let mut hovered = None;
+    egui::ScrollArea::horizontal()
+        .auto_shrink([false, true])
+        .drag_to_scroll(false)
+        .show_viewport(ui, |ui, viewport| {
+            let (rect, response) = ui.allocate_exact_size(
+                vec2(width as f32, height as f32) * CELL_SIZE,
+                Sense::click_and_drag(),
+            );
+            let cell_at = |pos: Pos2| {
+                let p = (pos - rect.min) / CELL_SIZE;
+                (p.x >= 0.0 && p.y >= 0.0)
+                    .then_some((p.x as usize, p.y as usize))
+                    .filter(|&(x, y)| x < width && y < height)
+            };
+            hovered = response.hover_pos().and_then(cell_at);
+            let pointer = response.interact_pointer_pos().and_then(cell_at);
+            let shift = ui.input(|i| i.modifiers.shift);
+            match state.tool {
+                Tool::Paint => {
+                    if response.is_pointer_button_down_on()
+                        && ui.input(|i| i.pointer.primary_down())
+                    {
+                        if let Some(cell) = pointer {
+                            let from = state.last_painted.unwrap_or(cell);
+                            for (x, y) in line_cells(from, cell) {
+                                if !state.locked.contains(&(x, y)) {
+                                    *codes.get_mut(x, y).unwrap() = state.paint;
+                                }
+                            }
+                            state.last_painted = Some(cell);
+                        } else {
+                            // don't connect the cells where the stroke left and reentered the grid
+                            state.last_painted = None;
                         }
-                        if ui
-                            .add_enabled(
-                                codes.width < MAX_FRAGMENTS,
-                                egui::Button::new("➡").small(),
-                            )
-                            .clicked()
-                        {
-                            codes.reshape(codes.width + 1, codes.height());
+                    } else {
+                        state.last_painted = None;
+                    }
+                    if response.secondary_clicked() {
+                        if let Some((x, y)) = pointer {
+                            state.paint = codes.get(x, y).unwrap();
                         }
-                        for x in 0..codes.width {
-                            ui.separator();
-                            ui.vertical(|ui| {
-                                for y in 0..codes.height() {
-                                    const MAX_CODE: u32 = 1023;
-                                    let value = codes.get_mut(x, y).unwrap();
-                                    ui.horizontal(|ui| {
-                                        ui.add(Slider::new(value, 0..=MAX_CODE));
-                                        if ui
-                                            .add_enabled(*value > 0, egui::Button::new("-").small())
-                                            .clicked()
-                                        {
-                                            *value -= 1;
-                                        }
-                                        if ui
-                                            .add_enabled(
-                                                *value < MAX_CODE,
-                                                egui::Button::new("+").small(),
-                                            )
-                                            .clicked()
-                                        {
-                                            *value += 1;
-                                        };
-                                    });
-                                }
-                            });
+                    }
+                }
+                Tool::Select => {
+                    if response.drag_started_by(egui::PointerButton::Primary) {
+                        let origin = ui.input(|i| i.pointer.press_origin()).and_then(cell_at);
+                        state.selecting = origin.map(|cell| (cell, cell));
+                    }
+                    if let (Some((start, _)), Some(cell)) = (state.selecting, pointer) {
+                        state.selecting = Some((start, cell));
+                    }
+                    if response.drag_stopped() {
+                        if let Some((a, b)) = state.selecting.take() {
+                            if !shift {
+                                state.selection.clear();
+                            }
+                            state.selection.extend(rect_cells(a, b));
                         }
-                    });
-                    ui.horizontal(|ui| {
-                        if ui
-                            .add_enabled(codes.height() > 1, egui::Button::new("⬆").small())
-                            .clicked()
-                        {
-                            codes.reshape(codes.width, codes.height() - 1);
+                    }
+                    if response.clicked() {
+                        if let Some(cell) = pointer {
+                            if !shift {
+                                state.selection.clear();
+                            }
+                            if !state.selection.remove(&cell) {
+                                state.selection.insert(cell);
+                            }
                         }
-                        if ui
-                            .add_enabled(
-                                codes.height() < MAX_LAYERS,
-                                egui::Button::new("⬇").small(),
-                            )
-                            .clicked()
-                        {
-                            codes.reshape(codes.width, codes.height() + 1);
+                    }
+                }
+            }
+            // the wheel steps the hovered code, or all selected ones if it is selected
+            if hovered.is_none() {
+                state.scroll = 0.0;
+            }
+            if let Some(cell) = hovered {
+                let scroll = ui.input(|i| i.raw_scroll_delta.y);
+                if scroll != 0.0 {
+                    ui.input_mut(|i| i.smooth_scroll_delta.y = 0.0);
+                }
+                // trackpads scroll a little every frame, so step once per notch worth of scrolling
+                state.scroll += scroll;
+                let steps = (state.scroll / SCROLL_STEP).trunc();
+                state.scroll -= steps * SCROLL_STEP;
+                if steps != 0.0 {
+                    let cells = if state.selection.contains(&cell) {
+                        state.selection.iter().copied().collect()
+                    } else {
+                        vec![cell]
+                    };
+                    for (x, y) in cells {
+                        if state.locked.contains(&(x, y)) {
+                            continue;
                         }
-                    });
-                });
-            });
+                        let ordering = ordering(&state.orderings, state.ordered, y);
+                        let Some(value) = codes.get_mut(x, y) else {
+                            continue;
+                        };
+                        let position = match ordering {
+                            Some(ordering) => ordering.positions[*value as usize],
+                            None => *value,
+                        };
+                        let position =
+                            (position as i64 + steps as i64).clamp(0, MAX_CODE as i64) as u32;
+                        *value = match ordering {
+                            Some(ordering) => ordering.codes[position as usize],
+                            None => position,
+                        };
+                    }
+                }
+            }
+            let painter = ui.painter_at(rect);
+            let cell_rect = |x: usize, y: usize| {
+                Rect::from_min_size(
+                    rect.min + vec2(x as f32, y as f32) * CELL_SIZE,
+                    vec2(CELL_SIZE, CELL_SIZE),
+                )
+            };
+            let selecting = state.selecting;
+            let is_selected = |x: usize, y: usize| {
+                state.selection.contains(&(x, y))
+                    || selecting.is_some_and(|(a, b)| {
+                        (a.0.min(b.0)..=a.0.max(b.0)).contains(&x)
+                            && (a.1.min(b.1)..=a.1.max(b.1)).contains(&y)
+                    })
+            };
+            for x in visible_columns(viewport, CELL_SIZE, width) {
+                for y in 0..height {
+                    let value = codes.get(x, y).unwrap();
+                    let position = match ordering(&state.orderings, state.ordered, y) {
+                        Some(ordering) => ordering.positions[value as usize],
+                        None => value,
+                    };
+                    let cell = cell_rect(x, y).shrink(0.5);
+                    painter.rect_filled(cell, 0.0, cell_color(position));
+                    if state.locked.contains(&(x, y)) {
+                        painter.circle_filled(cell.center(), CELL_SIZE / 6.0, Color32::BLACK);
+                    }
+                    if is_selected(x, y) {
+                        painter.rect_stroke(
+                            cell.shrink(0.5),
+                            0.0,
+                            Stroke::new(1.5, Color32::WHITE),
+                        );
+                    }
+                }
+            }
+            if let Some((x, y)) = hovered {
+                painter.rect_stroke(cell_rect(x, y), 0.0, ui.visuals().widgets.hovered.fg_stroke);
+            }
+        });
+    match hovered {
+        Some((x, y)) => ui.label(format!(
+            "frame {x}, codebook {y}: code {}",
+            codes.get(x, y).unwrap()
+        )),
+        None => ui.weak("scroll over a cell to step its code, or the selected codes"),
+    };
+}
+
+pub fn draw(ui: &mut egui::Ui, codes: &mut Codes, state: &mut EditorState) {
+    // forget locks and selections of cells that were removed, also by undo or dropped patches
+    let (width, height) = (codes.width(), codes.height());
+    state.locked.retain(|&(x, y)| x < width && y < height);
+    state.selection.retain(|&(x, y)| x < width && y < height);
+    ui.group(|ui| {
+        draw_random(ui, codes, state);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(codes.width() > 1, egui::Button::new("⬅").small())
+                .clicked()
+            {
+                codes.reshape(codes.width() - 1, codes.height());
+            }
+            if ui
+                .add_enabled(
+                    codes.width() < MAX_FRAGMENTS,
+                    egui::Button::new("➡").small(),
+                )
+                .clicked()
+            {
+                codes.reshape(codes.width() + 1, codes.height());
+            }
+            let mut width = codes.width();
+            if ui
+                .add(
+                    egui::DragValue::new(&mut width)
+                        .range(1..=MAX_FRAGMENTS)
+                        .suffix(" frames"),
+                )
+                .changed()
+            {
+                codes.reshape(width, codes.height());
+            }
+            ui.label(format!(
+                "{:.3} s",
+                (codes.width() * compute::FRAGMENT_SIZE) as f32 / compute::SAMPLE_RATE as f32
+            ));
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut state.layout, Layout::Sliders, "sliders");
+            ui.radio_value(&mut state.layout, Layout::Heatmap, "heatmap")
+                .on_hover_text("a compact grid of cells colored by their codes");
+        });
+        match state.layout {
+            Layout::Sliders => draw_sliders(ui, codes, state),
+            Layout::Heatmap => draw_heatmap(ui, codes, state),
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(codes.height() > 1, egui::Button::new("⬆").small())
+                .clicked()
+            {
+                codes.reshape(codes.width(), codes.height() - 1);
+            }
+            if ui
+                .add_enabled(codes.height() < MAX_LAYERS, egui::Button::new("⬇").small())
+                .clicked()
+            {
+                codes.reshape(codes.width(), codes.height() + 1);
+            }
+            ui.separator();
+            for (kbps, codebooks) in BANDWIDTHS {
+                if ui
+                    .selectable_label(codes.height() == codebooks, format!("{kbps} kbps"))
+                    .on_hover_text(format!("{codebooks} codebooks"))
+                    .clicked()
+                {
+                    codes.reshape(codes.width(), codebooks);
+                }
+            }
+            ui.separator();
+            ui.label(format!(
+                "{} codebooks, {} kbps",
+                codes.height(),
+                codes.bitrate()
+            ));
+        });
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordering_positions_are_inverse() {
+        let mut state = EditorState::default();
+        let codes: Vec<u32> = (0..=MAX_CODE)
+            .map(|i| (i * 7 + 3) % (MAX_CODE + 1))
+            .collect();
+        state.set_orderings(vec![codes.clone()]);
+        let ordering = &state.orderings[0];
+        for (position, &code) in codes.iter().enumerate() {
+            assert_eq!(ordering.positions[code as usize] as usize, position);
+            assert_eq!(ordering.codes[position], code);
+        }
+    }
+
+    #[test]
+    fn line_cells_have_no_gaps() {
+        assert_eq!(line_cells((3, 2), (3, 2)).collect::<Vec<_>>(), [(3, 2)]);
+        assert_eq!(
+            line_cells((0, 0), (3, 0)).collect::<Vec<_>>(),
+            [(0, 0), (1, 0), (2, 0), (3, 0)]
+        );
+        // steep and reversed
+        let cells: Vec<_> = line_cells((2, 7), (1, 0)).collect();
+        assert_eq!(cells.len(), 8);
+        assert_eq!((cells[0], cells[7]), ((2, 7), (1, 0)));
+        for w in cells.windows(2) {
+            assert_eq!(w[0].1 - w[1].1, 1);
+            assert!(w[0].0.abs_diff(w[1].0) <= 1);
+        }
+    }
+
+    #[test]
+    fn rect_cells_cover_the_rectangle() {
+        assert_eq!(rect_cells((4, 1), (4, 1)).collect::<Vec<_>>(), [(4, 1)]);
+        let mut cells: Vec<_> = rect_cells((2, 3), (1, 1)).collect();
+        cells.sort();
+        assert_eq!(cells, [(1, 1), (1, 2), (1, 3), (2, 1), (2, 2), (2, 3)]);
+        let mut reversed: Vec<_> = rect_cells((1, 3), (2, 1)).collect();
+        reversed.sort();
+        assert_eq!(reversed, cells);
+    }
+
+    #[test]
+    fn visible_columns_are_clamped() {
+        let viewport = |min, max| Rect::from_x_y_ranges(min..=max, 0.0..=100.0);
+        assert_eq!(visible_columns(viewport(0.0, 100.0), 10.0, 50), 0..10);
+        assert_eq!(visible_columns(viewport(15.0, 25.0), 10.0, 50), 1..3);
+        assert_eq!(visible_columns(viewport(-5.0, 1000.0), 10.0, 50), 0..50);
+        assert_eq!(visible_columns(viewport(600.0, 700.0), 10.0, 50), 50..50);
+    }
+}
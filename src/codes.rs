@@ -0,0 +1,243 @@
+//! Grids of codes, one row per codebook and one column per frame.
+
+use candle_core::{DType, Device, Tensor};
+use num::Integer as _;
+use rand::{Rng, SeedableRng as _};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::compute;
+
+/// About 13.6 seconds.
+pub const MAX_FRAGMENTS: usize = 1024;
+pub const MAX_LAYERS: usize = compute::CODEBOOKS;
+pub const MAX_CODE: u32 = 1023;
+/// Bandwidths the model was trained for, in kbps, with their number of codebooks.
+pub const BANDWIDTHS: [(f32, usize); 5] = [(1.5, 2), (3.0, 4), (6.0, 8), (12.0, 16), (24.0, 32)];
+
+/// An rng for `seed`, advancing it for the next operation so that each one can be reproduced.
+pub fn next_rng(seed: &mut u64) -> ChaCha8Rng {
+    let rng = ChaCha8Rng::seed_from_u64(*seed);
+    *seed = seed.wrapping_add(1);
+    rng
+}
+
+// TODO: use some existing type for 2d arrays instead?
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Codes {
+    codes: Vec<u32>,
+    width: usize,
+}
+
+impl Default for Codes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Codes {
+    pub fn new() -> Self {
+        Self {
+            codes: vec![0],
+            width: 1,
+        }
+    }
+
+    pub fn to_tensor(&self, device: &Device) -> anyhow::Result<Tensor> {
+        Ok(if self.width == 0 {
+            Tensor::zeros((0, 0), DType::U32, device)?
+        } else {
+            debug_assert!(self.codes.len() % self.width == 0);
+            let height = self.codes.len() / self.width;
+            Tensor::from_vec(self.codes.clone(), (height, self.width), device)?
+        })
+    }
+
+    /// Create from a `(codebooks, frames)` tensor of codes.
+    pub fn from_tensor(tensor: &Tensor) -> anyhow::Result<Self> {
+        let (height, width) = tensor.dims2()?;
+        anyhow::ensure!(width > 0 && height > 0, "empty codes");
+        Ok(Self {
+            codes: tensor.to_dtype(DType::U32)?.flatten_all()?.to_vec1()?,
+            width,
+        })
+    }
+
+    /// Create from codebook rows, which must all be the same length.
+    pub fn from_rows(rows: &[Vec<u32>]) -> anyhow::Result<Self> {
+        let width = rows.first().map_or(0, Vec::len);
+        anyhow::ensure!(width > 0, "empty codes");
+        anyhow::ensure!(
+            rows.iter().all(|row| row.len() == width),
+            "codebook rows have different lengths"
+        );
+        Ok(Self {
+            codes: rows.concat(),
+            width,
+        })
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u32]> {
+        self.codes.chunks_exact(self.width)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        debug_assert!(self.width > 0);
+        debug_assert!(!self.codes.is_empty());
+        if self.width == 0 {
+            0
+        } else {
+            self.codes.len() / self.width
+        }
+    }
+
+    /// Bitrate of the codes when streamed, in kbps.
+    pub fn bitrate(&self) -> f32 {
+        let bits_per_code = (MAX_CODE + 1).ilog2();
+        let frames_per_second = compute::SAMPLE_RATE as f32 / compute::FRAGMENT_SIZE as f32;
+        (self.height() as u32 * bits_per_code) as f32 * frames_per_second / 1000.0
+    }
+
+    /// Set the cells for which `pick(x, y)` returns true to random codes.
+    pub fn randomize(&mut self, rng: &mut impl Rng, mut pick: impl FnMut(usize, usize) -> bool) {
+        for (i, code) in self.codes.iter_mut().enumerate() {
+            let (y, x) = i.div_rem(&self.width);
+            if pick(x, y) {
+                *code = rng.gen_range(0..=MAX_CODE);
+            }
+        }
+    }
+
+    /// Move each of the cells for which `pick(x, y)` returns true by up to `amount`, with probability `rate`.
+    pub fn mutate(
+        &mut self,
+        rng: &mut impl Rng,
+        amount: u32,
+        rate: f32,
+        mut pick: impl FnMut(usize, usize) -> bool,
+    ) {
+        for (i, code) in self.codes.iter_mut().enumerate() {
+            let (y, x) = i.div_rem(&self.width);
+            if pick(x, y) && rng.gen::<f32>() < rate {
+                let delta = rng.gen_range(-(amount as i64)..=amount as i64);
+                *code = (*code as i64 + delta).clamp(0, MAX_CODE as i64) as u32;
+            }
+        }
+    }
+
+    /// Pick each cell from either `self` or `other`, which must have the same shape.
+    pub fn crossover(&self, other: &Codes, rng: &mut impl Rng) -> Codes {
+        assert_eq!(self.width, other.width);
+        assert_eq!(self.codes.len(), other.codes.len());
+        Self {
+            codes: self
+                .codes
+                .iter()
+                .zip(&other.codes)
+                .map(|(&a, &b)| if rng.gen() { a } else { b })
+                .collect(),
+            width: self.width,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<u32> {
+        if x < self.width && y < self.height() {
+            Some(self.codes[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut u32> {
+        if x < self.width && y < self.height() {
+            Some(&mut self.codes[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Resize the grid, keeping the codes that are still within it and setting new ones to 0.
+    /// Panics if either size is 0.
+    pub fn reshape(&mut self, width: usize, height: usize) {
+        assert!(width >= 1);
+        assert!(height >= 1);
+        self.codes = (0..height * width)
+            .map(|i| {
+                let (y, x) = i.div_rem(&width);
+                self.get(x, y).unwrap_or(0)
+            })
+            .collect();
+        self.width = width;
+    }
+}
+
+impl PartialEq for Codes {
+    fn eq(&self, other: &Self) -> bool {
+        self.codes == other.codes && self.width == other.width
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::SeedableRng as _;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn grid() -> Codes {
+        let mut codes = Codes::new();
+        codes.reshape(16, 8);
+        codes
+    }
+
+    /// Cells on the diagonal, that the tests leave alone.
+    pub(crate) fn is_locked(x: usize, y: usize) -> bool {
+        x == y
+    }
+
+    #[test]
+    fn randomize_is_reproducible() {
+        let randomized = |seed| {
+            let mut codes = grid();
+            codes.randomize(&mut ChaCha8Rng::seed_from_u64(seed), |x, y| {
+                !is_locked(x, y)
+            });
+            codes
+        };
+        assert_eq!(randomized(1), randomized(1));
+        assert_ne!(randomized(1), randomized(2));
+        let codes = randomized(1);
+        assert_ne!(codes, grid());
+        for y in 0..codes.height() {
+            assert_eq!(codes.get(y, y), Some(0));
+        }
+    }
+
+    #[test]
+    fn mutate_is_reproducible() {
+        let mut start = grid();
+        start.randomize(&mut ChaCha8Rng::seed_from_u64(0), |_, _| true);
+        let mutated = |seed, rate| {
+            let mut codes = start.clone();
+            codes.mutate(&mut ChaCha8Rng::seed_from_u64(seed), 16, rate, |x, y| {
+                !is_locked(x, y)
+            });
+            codes
+        };
+        assert_eq!(mutated(1, 0.5), mutated(1, 0.5));
+        assert_ne!(mutated(1, 0.5), mutated(2, 0.5));
+        assert_eq!(mutated(1, 0.0), start);
+        let codes = mutated(1, 1.0);
+        for (x, y) in (0..codes.width()).flat_map(|x| (0..codes.height()).map(move |y| (x, y))) {
+            let (before, after) = (start.get(x, y).unwrap(), codes.get(x, y).unwrap());
+            if is_locked(x, y) {
+                assert_eq!(before, after);
+            }
+            assert!(before.abs_diff(after) <= 16);
+        }
+    }
+}
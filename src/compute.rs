@@ -1,19 +1,26 @@
-use candle_core::{DType, Device, IndexOp as _, Tensor};
+use anyhow::Context as _;
+use candle_core::{DType, Device, IndexOp as _, Module as _, Tensor};
 use candle_transformers::models::encodec;
-use eframe::wasm_bindgen::JsCast;
-use gloo_utils::errors::JsError;
-use js_sys::wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
-use web_sys::{Blob, Response};
+use serde::{Deserialize, Serialize};
+#[cfg(target_arch = "wasm32")]
+use {
+    gloo_utils::errors::JsError,
+    js_sys::wasm_bindgen::JsCast,
+    js_sys::wasm_bindgen::JsValue,
+    wasm_bindgen_futures::JsFuture,
+    web_sys::{Blob, Response},
+};
 
 #[cfg(target_arch = "wasm32")]
-fn into_jserr(v: JsValue) -> JsError {
+pub(crate) fn into_jserr(v: JsValue) -> JsError {
     JsError::try_from(v).unwrap()
 }
 
 #[cfg(target_arch = "wasm32")]
 async fn fetch(url: &str) -> anyhow::Result<Vec<u8>> {
-    let response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(url))
+    // this runs in the decode worker, so there is no window
+    let scope: web_sys::WorkerGlobalScope = js_sys::global().unchecked_into();
+    let response = JsFuture::from(scope.fetch_with_str(url))
         .await
         .map_err(into_jserr)?
         .dyn_into::<Response>()
@@ -31,56 +38,498 @@ async fn fetch(url: &str) -> anyhow::Result<Vec<u8>> {
     Ok(js_sys::Uint8Array::new(&buffer).to_vec())
 }
 
+/// Huggingface id of the model the codes are meant for.
+pub const MODEL_ID: &str = "facebook/encodec_24khz";
+/// Sample rate of the encodec 24khz model.
+pub const SAMPLE_RATE: u32 = 24000;
+/// Number of samples generated by each frame of codes.
+pub const FRAGMENT_SIZE: usize = 320;
+/// Number of codebooks of the model.
+pub const CODEBOOKS: usize = 32;
+/// Frames decoded before the blended periods in [`LoopMode::Blend`].
+const CONTEXT_FRAMES: usize = 16;
+pub const DEFAULT_WARMUP_FRAMES: usize = 16;
+pub const MAX_WARMUP_FRAMES: usize = 128;
+
+/// How the codes are decoded into a loop without a click where it wraps around.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum LoopMode {
+    /// Decode two periods of the loop and crossfade between them over the whole loop.
+    Blend,
+    /// Decode the loop once, preceded by this many frames of its own end, so the decoder state has wrapped around when the loop starts.
+    WarmUp(usize),
+}
+
+impl Default for LoopMode {
+    fn default() -> Self {
+        Self::WarmUp(DEFAULT_WARMUP_FRAMES)
+    }
+}
+
+/// Environment variable that can be set to a local `model.safetensors` to use instead of fetching it from huggingface.
+#[cfg(not(target_arch = "wasm32"))]
+pub const MODEL_PATH_ENV: &str = "ENCODEC_MODEL_PATH";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn model_path() -> anyhow::Result<std::path::PathBuf> {
+    match std::env::var_os(MODEL_PATH_ENV) {
+        Some(path) => {
+            let path = std::path::PathBuf::from(path);
+            anyhow::ensure!(
+                path.is_file(),
+                "model file {path:?} (from {MODEL_PATH_ENV}) does not exist"
+            );
+            Ok(path)
+        }
+        None => Ok(hf_hub::api::sync::Api::new()?
+            .model(MODEL_ID.to_string())
+            .get("model.safetensors")
+            .with_context(|| {
+                format!("unable to download model, set {MODEL_PATH_ENV} to use a local file")
+            })?),
+    }
+}
+
+/// Which variants of the codes [`Compute::decode_variant`] decodes, to show what each codebook contributes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Contribution {
+    /// For each codebook, all codebooks up to and including it.
+    Cumulative,
+    /// For each codebook, all codebooks except it.
+    LeaveOneOut,
+}
+
+/// Distribution of the latent the decoder sees, for codes picked uniformly at random.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LatentStats {
+    /// Mean of each dimension.
+    pub mean: Vec<f32>,
+    /// Standard deviation of each dimension.
+    pub std: Vec<f32>,
+    /// Unit length principal axes, the one with the most variance first.
+    pub axes: Vec<Vec<f32>>,
+    /// Standard deviation along each axis.
+    pub axis_std: Vec<f32>,
+}
+
 pub struct Compute {
-    model: encodec::Model,
+    encoder: encodec::Encoder,
+    quantizer: encodec::ResidualVectorQuantizer,
+    decoder: encodec::Decoder,
+    /// `(codebooks, codebook size, codebook dimension)` embeddings of the quantizer.
+    embeddings: Tensor,
     device: Device,
 }
 
 impl Compute {
     pub async fn new() -> anyhow::Result<Self> {
         let device = candle_core::Device::Cpu;
-        let model_path = "model.safetensors";
         #[cfg(target_arch = "wasm32")]
         let vb = candle_nn::VarBuilder::from_buffered_safetensors(
-            fetch(model_path).await?,
+            fetch("model.safetensors").await?,
             DType::F32,
             &device,
         )?;
         #[cfg(not(target_arch = "wasm32"))]
         let vb = unsafe {
-            let model_path = hf_hub::api::sync::Api::new()?
-                .model("facebook/encodec_24khz".to_string())
-                .get("model.safetensors")?;
-            candle_nn::VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &device)?
+            let model_path = model_path()?;
+            candle_nn::VarBuilder::from_mmaped_safetensors(&[&model_path], DType::F32, &device)
+                .with_context(|| format!("unable to read {model_path:?}"))?
         };
         let config = encodec::Config::default();
-        let model = encodec::Model::new(&config, vb)?;
-        Ok(Self { model, device })
+        // the parts of the model are used separately, to be able to decode latents directly
+        let (encoder, quantizer, decoder) = (|| {
+            anyhow::Ok((
+                encodec::Encoder::new(&config, vb.pp("encoder"))?,
+                encodec::ResidualVectorQuantizer::new(&config, vb.pp("quantizer"))?,
+                encodec::Decoder::new(&config, vb.pp("decoder"))?,
+            ))
+        })()
+        .context("model weights do not match the encodec 24khz configuration")?;
+        // the quantizer doesn't expose its codebooks, so read them separately
+        let embedding_shape = (
+            config.codebook_size,
+            config.codebook_dim.unwrap_or(config.hidden_size),
+        );
+        let embeddings = Tensor::stack(
+            &(0..CODEBOOKS)
+                .map(|i| {
+                    vb.pp("quantizer.layers")
+                        .pp(i)
+                        .pp("codebook")
+                        .get(embedding_shape, "embed")
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            0,
+        )?;
+        Ok(Self {
+            encoder,
+            quantizer,
+            decoder,
+            embeddings,
+            device,
+        })
+    }
+
+    /// Decode a `(codebooks, frames)` tensor of codes into a loop.
+    pub fn decode_codes(&self, codes: &Tensor, mode: LoopMode) -> anyhow::Result<Vec<f32>> {
+        self.decode_latent(&self.codes_to_latent(codes)?, mode)
     }
 
-    pub fn decode_codes(&self, codes: &Tensor) -> anyhow::Result<Vec<f32>> {
+    /// The `(dimension, frames)` latent of a `(codebooks, frames)` tensor of codes, which is the sum of their codebook embeddings.
+    pub fn codes_to_latent(&self, codes: &Tensor) -> anyhow::Result<Tensor> {
         assert!(codes.dtype() == DType::U32);
-        assert!(codes.shape().dims2().is_ok());
-        // TODO: perhaps we don't need to concat all of the fragments? just the edges?
-        let code_tensor = Tensor::cat(&[codes, codes, codes, codes], 1)?.unsqueeze(0)?;
-        let all_samples = self.model.decode(&code_tensor)?.i(0)?.i(0)?;
-        const FRAGMENT_SIZE: usize = 320;
-        let buffer_size = FRAGMENT_SIZE * codes.shape().dims2()?.1;
-        let weights = Tensor::from_vec(
-            (0..buffer_size)
-                .map(|i| i as f32 / (buffer_size as f32 - 1.0))
-                .collect(),
-            (buffer_size,),
-            &self.device,
-        )?;
-        let samples = ((all_samples.i(buffer_size..(2 * buffer_size))? * &weights)?
-            + (all_samples.i((2 * buffer_size)..(3 * buffer_size))? * (1.0 - weights))?)?;
+        Ok(self.quantizer.decode(&codes.unsqueeze(1)?)?.i(0)?)
+    }
+
+    /// Decode the variant of the codes for `codebook`, with some codebooks left out, or all of the codes if `None`.
+    /// Unlike [`Compute::decode_codes`] the DC offset is kept, so that variants can be subtracted exactly.
+    pub fn decode_variant(
+        &self,
+        codes: &Tensor,
+        mode: LoopMode,
+        contribution: Contribution,
+        codebook: Option<usize>,
+    ) -> anyhow::Result<Vec<f32>> {
+        // the latent is the sum of the embeddings of each codebook, so they can be added and removed separately
+        let latent = match (codebook, contribution) {
+            (None, _) => self.codes_to_latent(codes)?,
+            (Some(i), Contribution::Cumulative) => self.codes_to_latent(&codes.i(..=i)?)?,
+            (Some(i), Contribution::LeaveOneOut) => {
+                (self.codes_to_latent(codes)? - self.codes_to_latent(&codes.i(i..=i)?)?)?
+            }
+        };
+        Ok(self.decode_loop(&latent, mode)?.to_vec1()?)
+    }
+
+    /// Decode a `(dimension, frames)` latent into a loop, without quantizing it.
+    pub fn decode_latent(&self, latent: &Tensor, mode: LoopMode) -> anyhow::Result<Vec<f32>> {
+        let samples = self.decode_loop(latent, mode)?;
         let mean = samples.mean_all()?;
         let dc0 = (samples.broadcast_sub(&mean))?;
         Ok(dc0.to_vec1()?)
     }
 
+    /// Decode a `(dimension, frames)` latent into a loop, including its DC offset.
+    fn decode_loop(&self, latent: &Tensor, mode: LoopMode) -> anyhow::Result<Tensor> {
+        let (_, frames) = latent.shape().dims2()?;
+        let buffer_size = FRAGMENT_SIZE * frames;
+        let samples = match mode {
+            LoopMode::Blend => {
+                // decode two periods with some context on either side, then blend them so the ends meet.
+                // the context is limited so long loops cost about twice their length rather than four times.
+                let context = frames.min(CONTEXT_FRAMES);
+                let all_samples = self.decode_circular(latent, context, context + 2 * frames)?;
+                let start = FRAGMENT_SIZE * context;
+                let weights = Tensor::from_vec(
+                    (0..buffer_size)
+                        .map(|i| i as f32 / (buffer_size as f32 - 1.0).max(1.0))
+                        .collect(),
+                    (buffer_size,),
+                    &self.device,
+                )?;
+                ((all_samples.i(start..start + buffer_size)? * &weights)?
+                    + (all_samples.i(start + buffer_size..start + 2 * buffer_size)?
+                        * (1.0 - weights))?)?
+            }
+            LoopMode::WarmUp(warmup) => {
+                // the decoder is causal, so the start of the loop comes out as if the loop had already been playing
+                let all_samples = self.decode_circular(latent, warmup, warmup + frames)?;
+                let start = FRAGMENT_SIZE * warmup;
+                all_samples.i(start..start + buffer_size)?
+            }
+        };
+        Ok(samples)
+    }
+
+    /// Decode `keep + 1` frames of the latent repeated in a circle, starting `before` frames before its first one.
+    /// Returns the samples of the first `keep` of them, the last one is only lookahead.
+    fn decode_circular(
+        &self,
+        latent: &Tensor,
+        before: usize,
+        keep: usize,
+    ) -> anyhow::Result<Tensor> {
+        let (_, frames) = latent.shape().dims2()?;
+        // a frame of lookahead after what is kept, so the end of it is decoded as if more followed
+        let total = keep + 1;
+        let indices = Tensor::from_vec(
+            circular_indices(frames, before, total),
+            (total,),
+            &self.device,
+        )?;
+        let latent = latent.index_select(&indices, 1)?.unsqueeze(0)?;
+        Ok(self
+            .decoder
+            .forward(&latent)?
+            .i(0)?
+            .i((0, ..keep * FRAGMENT_SIZE))?)
+    }
+
+    /// Encode mono samples at [`SAMPLE_RATE`] into a `(codebooks, frames)` tensor of codes.
+    pub fn encode_samples(&self, samples: &[f32]) -> anyhow::Result<Tensor> {
+        anyhow::ensure!(!samples.is_empty(), "no samples to encode");
+        let input = Tensor::from_slice(samples, (1, 1, samples.len()), &self.device)?;
+        let latent = self.encoder.forward(&input)?;
+        Ok(self
+            .quantizer
+            .encode(&latent)?
+            .i((.., 0))?
+            .to_dtype(DType::U32)?)
+    }
+
+    /// For each codebook, its codes ordered so that neighbours have similar embeddings.
+    pub fn code_orderings(&self) -> anyhow::Result<Vec<Vec<u32>>> {
+        (0..self.embeddings.dim(0)?)
+            .map(|i| {
+                let embed = self.embeddings.i(i)?;
+                let norms = embed.sqr()?.sum_keepdim(1)?;
+                let distances = (norms.broadcast_add(&norms.t()?)?
+                    - (embed.matmul(&embed.t()?)? * 2.0)?)?
+                    .to_vec2::<f32>()?;
+                // start at an outlier, so the chain is less likely to have to jump back across the codebook
+                let start = embed
+                    .broadcast_sub(&embed.mean_keepdim(0)?)?
+                    .sqr()?
+                    .sum(1)?
+                    .argmax(0)?
+                    .to_scalar::<u32>()? as usize;
+                Ok(nearest_neighbour_chain(&distances, start))
+            })
+            .collect()
+    }
+
+    /// Statistics of the latent, with the `axes` principal axes.
+    pub fn latent_stats(&self, axes: usize) -> anyhow::Result<LatentStats> {
+        // the codebooks are picked independently, so both the means and the covariances add up
+        let mut mean = None;
+        let mut covariance = None;
+        for i in 0..self.embeddings.dim(0)? {
+            let embed = self.embeddings.i(i)?;
+            let codebook_mean = embed.mean_keepdim(0)?;
+            let centered = embed.broadcast_sub(&codebook_mean)?;
+            let codebook_covariance = (centered.t()?.matmul(&centered)? / embed.dim(0)? as f64)?;
+            mean = Some(match mean {
+                Some(mean) => (mean + codebook_mean)?,
+                None => codebook_mean,
+            });
+            covariance = Some(match covariance {
+                Some(covariance) => (covariance + codebook_covariance)?,
+                None => codebook_covariance,
+            });
+        }
+        let (mean, covariance) = mean.zip(covariance).context("no codebooks")?;
+        let covariance = covariance.to_vec2::<f32>()?;
+        let std = (0..covariance.len())
+            .map(|d| covariance[d][d].max(0.0).sqrt())
+            .collect();
+        let (axes, axis_std) = principal_axes(covariance, axes)
+            .into_iter()
+            .map(|(axis, variance)| (axis, variance.max(0.0).sqrt()))
+            .unzip();
+        Ok(LatentStats {
+            mean: mean.i(0)?.to_vec1()?,
+            std,
+            axes,
+            axis_std,
+        })
+    }
+
     pub fn device(&self) -> &Device {
         &self.device
     }
 }
+
+/// Indices of `total` frames of a loop of `frames` frames, starting `before` frames before its first one.
+fn circular_indices(frames: usize, before: usize, total: usize) -> Vec<u32> {
+    (0..total)
+        .map(|i| ((i + frames * before.div_ceil(frames) - before) % frames) as u32)
+        .collect()
+}
+
+/// The `count` largest eigenvectors and eigenvalues of a symmetric positive semi-definite matrix,
+/// using power iteration and removing each found eigenvector from the matrix.
+fn principal_axes(mut matrix: Vec<Vec<f32>>, count: usize) -> Vec<(Vec<f32>, f32)> {
+    const ITERATIONS: usize = 200;
+    let n = matrix.len();
+    let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    (0..count.min(n))
+        .map(|_| {
+            // anything that isn't orthogonal to the eigenvector works as a start
+            let mut vector: Vec<f32> = (0..n).map(|i| 1.0 + i as f32 / n as f32).collect();
+            let mut value = 0.0;
+            for _ in 0..ITERATIONS {
+                let next: Vec<f32> = matrix.iter().map(|row| dot(row, &vector)).collect();
+                value = dot(&next, &next).sqrt();
+                if value == 0.0 {
+                    break;
+                }
+                vector = next.into_iter().map(|x| x / value).collect();
+            }
+            for (i, row) in matrix.iter_mut().enumerate() {
+                for (j, x) in row.iter_mut().enumerate() {
+                    *x -= value * vector[i] * vector[j];
+                }
+            }
+            (vector, value)
+        })
+        .collect()
+}
+
+/// Visit every point starting at `start`, going to the nearest unvisited one at each step.
+fn nearest_neighbour_chain(distances: &[Vec<f32>], start: usize) -> Vec<u32> {
+    let mut visited = vec![false; distances.len()];
+    let mut chain = Vec::with_capacity(distances.len());
+    let mut current = start;
+    loop {
+        visited[current] = true;
+        chain.push(current as u32);
+        let next = distances[current]
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !visited[i])
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        match next {
+            Some((i, _)) => current = i,
+            None => return chain,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    /// Some deterministic pseudo random codes.
+    fn codes(seed: u32, codebooks: usize, frames: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..codebooks * frames)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 16) % 1024
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chain_visits_nearest_codes_first() {
+        // points on a line
+        let points = [0.0f32, 10.0, 1.0, 11.0, 2.0];
+        let distances: Vec<Vec<f32>> = points
+            .iter()
+            .map(|a| points.iter().map(|b| (a - b).abs()).collect())
+            .collect();
+        assert_eq!(nearest_neighbour_chain(&distances, 0), [0, 2, 4, 1, 3]);
+        for start in 0..points.len() {
+            let chain = nearest_neighbour_chain(&distances, start);
+            assert_eq!(chain[0] as usize, start);
+            let mut sorted = chain.clone();
+            sorted.sort();
+            assert_eq!(sorted, [0, 1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn principal_axes_of_known_matrices() {
+        let assert_axis =
+            |(axis, value): &(Vec<f32>, f32), expected: &[f32], expected_value: f32| {
+                assert!(
+                    (value - expected_value).abs() < 1e-4,
+                    "{value} != {expected_value}"
+                );
+                // the sign of an eigenvector is arbitrary
+                let dot: f32 = axis.iter().zip(expected).map(|(a, b)| a * b).sum();
+                assert!((dot.abs() - 1.0).abs() < 1e-4, "{axis:?} != ±{expected:?}");
+            };
+        let diagonal = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 9.0, 0.0],
+            vec![0.0, 0.0, 4.0],
+        ];
+        let axes = principal_axes(diagonal, 3);
+        assert_axis(&axes[0], &[0.0, 1.0, 0.0], 9.0);
+        assert_axis(&axes[1], &[0.0, 0.0, 1.0], 4.0);
+        assert_axis(&axes[2], &[1.0, 0.0, 0.0], 1.0);
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        let axes = principal_axes(vec![vec![2.0, 1.0], vec![1.0, 2.0]], 4);
+        assert_eq!(axes.len(), 2);
+        assert_axis(&axes[0], &[h, h], 3.0);
+        assert_axis(&axes[1], &[h, -h], 1.0);
+    }
+
+    /// How much larger the jump at a loop boundary may be than the largest one within the loop, or than that of a blended loop.
+    /// Random codes make for noisy loops, so the boundary can happen to be a bit rougher than the rest.
+    const SEAM_TOLERANCE: f32 = 1.5;
+
+    #[test]
+    fn circular_indices_start_the_loop_after_the_warm_up() {
+        let frames = 12;
+        for before in [0, 1, 11, 12, 13, DEFAULT_WARMUP_FRAMES, MAX_WARMUP_FRAMES] {
+            let keep = before + frames;
+            let indices = circular_indices(frames, before, keep + 1);
+            assert_eq!(indices.len(), keep + 1);
+            // each frame is followed by the next one around the loop
+            for w in indices.windows(2) {
+                assert_eq!(
+                    w[1] as usize,
+                    (w[0] as usize + 1) % frames,
+                    "before {before}"
+                );
+            }
+            // what decode_latent crops out is the loop, followed by lookahead from its start
+            let expected: Vec<u32> = (0..frames as u32).chain([0]).collect();
+            assert_eq!(indices[before..], expected, "before {before}");
+        }
+    }
+
+    /// The jump from the last sample back to the first, and the largest jump between neighbouring samples within the loop.
+    fn jumps(samples: &[f32]) -> (f32, f32) {
+        let seam = (samples[0] - samples[samples.len() - 1]).abs();
+        let within = samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        (seam, within)
+    }
+
+    #[test]
+    #[ignore = "needs the model, from huggingface or ENCODEC_MODEL_PATH"]
+    fn warm_up_loop_has_no_seam() {
+        let compute = pollster::block_on(Compute::new()).unwrap();
+        let (codebooks, frames) = (8, 12);
+        let mut naive_seams = 0.0;
+        let mut blend_seams = 0.0;
+        let mut warm_up_seams = 0.0;
+        for seed in 0..8 {
+            let codes = Tensor::from_vec(
+                codes(seed, codebooks, frames),
+                (codebooks, frames),
+                compute.device(),
+            )
+            .unwrap();
+            let naive = compute.decode_codes(&codes, LoopMode::WarmUp(0)).unwrap();
+            let blend = compute.decode_codes(&codes, LoopMode::Blend).unwrap();
+            let warm_up = compute
+                .decode_codes(&codes, LoopMode::WarmUp(DEFAULT_WARMUP_FRAMES))
+                .unwrap();
+            assert_eq!(warm_up.len(), frames * FRAGMENT_SIZE);
+            let (seam, within) = jumps(&warm_up);
+            assert!(
+                seam <= within * SEAM_TOLERANCE,
+                "seed {seed}: jump at the loop boundary {seam} is much larger than any within the loop {within}"
+            );
+            naive_seams += jumps(&naive).0;
+            blend_seams += jumps(&blend).0;
+            warm_up_seams += seam;
+        }
+        assert!(
+            warm_up_seams < naive_seams,
+            "warm-up seams {warm_up_seams} not smaller than without warm-up {naive_seams}"
+        );
+        // blending hides the seam by construction, the warm-up should get about as close
+        assert!(
+            warm_up_seams <= blend_seams * SEAM_TOLERANCE,
+            "warm-up seams {warm_up_seams} much larger than when blending {blend_seams}"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Shows what each codebook adds to the sound, by decoding the codes with codebooks left out.
+
+use egui::{epaint, pos2, vec2, Color32, Rect, Stroke};
+
+use crate::{
+    codes::Codes,
+    compute::{Contribution, LoopMode},
+    worker::{Request, Worker},
+};
+
+const PLOT_SIZE: egui::Vec2 = vec2(300.0, 30.0);
+
+/// A decoded variant and what its codebook adds, both without DC offset.
+struct Row {
+    variant: Vec<f32>,
+    difference: Vec<f32>,
+}
+
+struct Analysis {
+    codes: Codes,
+    mode: LoopMode,
+    contribution: Contribution,
+    /// All of the codes, with DC offset, if needed for the differences.
+    full: Option<Result<Vec<f32>, String>>,
+    /// Variants of the codes, one per codebook, see [`Contribution`]. With DC offset, `None` until decoded.
+    variants: Vec<Option<Result<Vec<f32>, String>>>,
+    /// `None` until the variants it is made from are decoded.
+    rows: Vec<Option<Result<Row, String>>>,
+}
+
+impl Analysis {
+    /// Make the rows whose variants have been decoded.
+    fn update_rows(&mut self) {
+        for i in 0..self.rows.len() {
+            if self.rows[i].is_none() {
+                self.rows[i] = self.make_row(i);
+            }
+        }
+    }
+
+    /// The row of codebook `i`, if the variants it is made from have been decoded.
+    fn make_row(&self, i: usize) -> Option<Result<Row, String>> {
+        let variant = self.variants[i].as_ref()?;
+        // the difference is taken before removing the DC offset, so it is exactly what the codebook adds
+        let difference = match (self.contribution, i.checked_sub(1)) {
+            (Contribution::Cumulative, None) => variant.clone(),
+            (Contribution::Cumulative, Some(previous)) => {
+                subtract(variant, self.variants[previous].as_ref()?)
+            }
+            (Contribution::LeaveOneOut, _) => subtract(self.full.as_ref()?, variant),
+        };
+        Some(difference.and_then(|difference| {
+            Ok(Row {
+                variant: remove_mean(variant.clone()?),
+                difference: remove_mean(difference),
+            })
+        }))
+    }
+}
+
+pub enum Event {
+    Audition(Vec<f32>),
+    /// Go back to playing the codes.
+    StopAudition,
+}
+
+pub struct ContributionView {
+    contribution: Contribution,
+    analysis: Option<Analysis>,
+    /// Codebook being auditioned, and whether it is the difference rather than the variant.
+    playing: Option<(usize, bool)>,
+}
+
+impl Default for ContributionView {
+    fn default() -> Self {
+        Self {
+            contribution: Contribution::Cumulative,
+            analysis: None,
+            playing: None,
+        }
+    }
+}
+
+impl ContributionView {
+    /// Set a decoded variant, ignoring those of earlier analyses.
+    pub fn set_variant(
+        &mut self,
+        codes: &Codes,
+        mode: LoopMode,
+        contribution: Contribution,
+        codebook: Option<usize>,
+        samples: Result<Vec<f32>, String>,
+    ) {
+        let Some(analysis) = &mut self.analysis else {
+            return;
+        };
+        if (&analysis.codes, analysis.mode, analysis.contribution) != (codes, mode, contribution) {
+            return;
+        }
+        match codebook {
+            None => analysis.full = Some(samples),
+            Some(i) => {
+                if let Some(variant) = analysis.variants.get_mut(i) {
+                    *variant = Some(samples);
+                }
+            }
+        }
+        analysis.update_rows();
+    }
+
+    /// Stop showing which codebook is playing, since something else is.
+    pub fn stop_audition(&mut self) {
+        self.playing = None;
+    }
+
+    fn analyze(&mut self, worker: &mut Worker, codes: &Codes, mode: LoopMode) {
+        let contribution = self.contribution;
+        let codebooks = codes.height();
+        let request = |codebook| Request::DecodeVariant {
+            codes: codes.clone(),
+            mode,
+            contribution,
+            codebook,
+        };
+        if contribution == Contribution::LeaveOneOut {
+            worker.request(request(None));
+        }
+        for i in 0..codebooks {
+            worker.request(request(Some(i)));
+        }
+        self.analysis = Some(Analysis {
+            codes: codes.clone(),
+            mode,
+            contribution,
+            full: None,
+            variants: vec![None; codebooks],
+            rows: (0..codebooks).map(|_| None).collect(),
+        });
+        self.playing = None;
+    }
+}
+
+fn subtract(
+    a: &Result<Vec<f32>, String>,
+    b: &Result<Vec<f32>, String>,
+) -> Result<Vec<f32>, String> {
+    Ok(a.as_ref()?
+        .iter()
+        .zip(b.as_ref()?)
+        .map(|(a, b)| a - b)
+        .collect())
+}
+
+fn remove_mean(mut samples: Vec<f32>) -> Vec<f32> {
+    let mean = samples.iter().sum::<f32>() / samples.len().max(1) as f32;
+    for s in &mut samples {
+        *s -= mean;
+    }
+    samples
+}
+
+fn rms_db(samples: &[f32]) -> f32 {
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+    10.0 * mean_square.max(1e-12).log10()
+}
+
+fn draw_plot(ui: &mut egui::Ui, samples: &[f32]) {
+    let (_, rect) = ui.allocate_space(PLOT_SIZE);
+    let p = ui.painter_at(rect);
+    p.rect_filled(rect, 3.0, Color32::BLACK);
+    if samples.len() < 2 {
+        return;
+    }
+    let to_rect = egui::emath::RectTransform::from_to(
+        Rect::from_x_y_ranges(0.0..=(samples.len() - 1) as f32, 1.0..=-1.0),
+        rect,
+    );
+    // a few points per pixel is enough
+    let step = (samples.len() / (4 * PLOT_SIZE.x as usize)).max(1);
+    let line = samples
+        .iter()
+        .enumerate()
+        .step_by(step)
+        .map(|(x, &y)| to_rect * pos2(x as f32, y.clamp(-1.0, 1.0)))
+        .collect();
+    p.add(epaint::Shape::line(line, Stroke::new(1.0, Color32::GRAY)));
+}
+
+/// `codes` are the ones in the editor.
+pub fn draw(
+    ui: &mut egui::Ui,
+    view: &mut ContributionView,
+    worker: &mut Worker,
+    codes: &Codes,
+    mode: LoopMode,
+) -> Option<Event> {
+    let mut event = None;
+    ui.horizontal(|ui| {
+        ui.radio_value(
+            &mut view.contribution,
+            Contribution::Cumulative,
+            "cumulative",
+        )
+        .on_hover_text("compare each codebook with the ones before it");
+        ui.radio_value(
+            &mut view.contribution,
+            Contribution::LeaveOneOut,
+            "leave one out",
+        )
+        .on_hover_text("compare all codebooks with all but one");
+        if ui
+            .button("analyze")
+            .on_hover_text("decode the codes once per codebook")
+            .clicked()
+        {
+            view.analyze(worker, codes, mode);
+        }
+        if view.playing.is_some() && ui.button("⏹").on_hover_text("stop auditioning").clicked() {
+            view.playing = None;
+            event = Some(Event::StopAudition);
+        }
+    });
+    let Some(analysis) = &view.analysis else {
+        return event;
+    };
+    if &analysis.codes != codes || analysis.mode != mode {
+        ui.weak("the codes have changed since they were analyzed");
+    }
+    egui::Grid::new("contributions")
+        .striped(true)
+        .show(ui, |ui| {
+            for (i, row) in analysis.rows.iter().enumerate() {
+                ui.label(format!("codebook {i}"));
+                match row {
+                    Some(Ok(Row {
+                        variant,
+                        difference,
+                    })) => {
+                        ui.label(format!("{:.1} dB", rms_db(difference)))
+                            .on_hover_text("level of what the codebook adds");
+                        draw_plot(ui, difference);
+                        if ui
+                            .selectable_label(view.playing == Some((i, true)), "▶ difference")
+                            .clicked()
+                        {
+                            view.playing = Some((i, true));
+                            event = Some(Event::Audition(difference.clone()));
+                        }
+                        let variant_label = match analysis.contribution {
+                            Contribution::Cumulative => format!("▶ 0..={i}"),
+                            Contribution::LeaveOneOut => format!("▶ without {i}"),
+                        };
+                        if ui
+                            .selectable_label(view.playing == Some((i, false)), variant_label)
+                            .clicked()
+                        {
+                            view.playing = Some((i, false));
+                            event = Some(Event::Audition(variant.clone()));
+                        }
+                    }
+                    Some(Err(e)) => {
+                        ui.label("⚠")
+                            .on_hover_text(format!("unable to decode: {e}"));
+                    }
+                    None => {
+                        ui.add(egui::Spinner::new());
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    event
+}
@@ -0,0 +1,329 @@
+//! Breeding sounds by auditioning a population of codes and picking favorites.
+//!
+//! The next generation keeps the picked candidates and fills up with crossovers of them, which are then mutated.
+
+use rand::{seq::SliceRandom as _, Rng};
+
+use crate::{
+    codes::{next_rng, Codes},
+    compute::LoopMode,
+    worker::{Request, Worker},
+};
+
+pub const DEFAULT_POPULATION: usize = 8;
+pub const MAX_POPULATION: usize = 32;
+
+struct Candidate {
+    codes: Codes,
+    /// `None` until the worker has decoded it, or failed to.
+    samples: Option<Result<Vec<f32>, String>>,
+    picked: bool,
+}
+
+pub enum Event {
+    /// Play the samples of a candidate.
+    Audition(Vec<f32>),
+    /// Open a candidate in the editor.
+    Edit(Codes),
+}
+
+pub struct Evolution {
+    candidates: Vec<Candidate>,
+    generation: usize,
+    population: usize,
+    /// Seed of the next generation, see [`next_rng`].
+    seed: u64,
+    mutation_amount: u32,
+    mutation_rate: f32,
+    /// Index of the candidate being auditioned.
+    playing: Option<usize>,
+}
+
+impl Default for Evolution {
+    fn default() -> Self {
+        Self {
+            candidates: Vec::new(),
+            generation: 0,
+            population: DEFAULT_POPULATION,
+            seed: 0,
+            mutation_amount: 32,
+            mutation_rate: 0.1,
+            playing: None,
+        }
+    }
+}
+
+impl Evolution {
+    /// Set the samples of a decoded candidate, ignoring candidates of earlier generations.
+    pub fn set_samples(
+        &mut self,
+        generation: usize,
+        index: usize,
+        samples: Result<Vec<f32>, String>,
+    ) {
+        if generation == self.generation {
+            if let Some(candidate) = self.candidates.get_mut(index) {
+                candidate.samples = Some(samples);
+            }
+        }
+    }
+
+    /// Stop showing which candidate is playing, since something else is.
+    pub fn stop_audition(&mut self) {
+        self.playing = None;
+    }
+
+    /// Start over with `codes` and mutations of it, leaving the locked cells alone.
+    fn seed_population(&mut self, codes: &Codes, is_locked: impl Fn(usize, usize) -> bool) {
+        let mut rng = next_rng(&mut self.seed);
+        let mut population = vec![codes.clone()];
+        while population.len() < self.population {
+            let mut child = codes.clone();
+            child.mutate(
+                &mut rng,
+                self.mutation_amount,
+                self.mutation_rate,
+                |x, y| !is_locked(x, y),
+            );
+            population.push(child);
+        }
+        self.set_population(population);
+    }
+
+    /// Keep the picked candidates and fill up with mutated crossovers of them.
+    fn next_generation(&mut self, is_locked: impl Fn(usize, usize) -> bool) {
+        let parents: Vec<Codes> = self
+            .candidates
+            .iter()
+            .filter(|c| c.picked)
+            .map(|c| c.codes.clone())
+            .collect();
+        if parents.is_empty() {
+            return;
+        }
+        let population = breed(
+            &parents,
+            self.population,
+            &mut next_rng(&mut self.seed),
+            self.mutation_amount,
+            self.mutation_rate,
+            is_locked,
+        );
+        self.set_population(population);
+    }
+
+    fn set_population(&mut self, population: Vec<Codes>) {
+        self.generation += 1;
+        self.playing = None;
+        self.candidates = population
+            .into_iter()
+            .map(|codes| Candidate {
+                codes,
+                samples: None,
+                picked: false,
+            })
+            .collect();
+    }
+
+    fn request_decodes(&self, worker: &mut Worker, mode: LoopMode) {
+        for (index, candidate) in self.candidates.iter().enumerate() {
+            worker.request(Request::DecodeCandidate {
+                generation: self.generation,
+                index,
+                codes: candidate.codes.clone(),
+                mode,
+            });
+        }
+    }
+}
+
+/// The `parents` followed by mutated crossovers of them, at least `size` in total.
+/// Locked cells are taken from one of the parents of each child, and aren't mutated.
+fn breed(
+    parents: &[Codes],
+    size: usize,
+    rng: &mut impl Rng,
+    mutation_amount: u32,
+    mutation_rate: f32,
+    is_locked: impl Fn(usize, usize) -> bool,
+) -> Vec<Codes> {
+    let mut population = parents.to_vec();
+    while population.len() < size.max(parents.len() + 1) {
+        let a = parents.choose(rng).unwrap();
+        let b = parents.choose(rng).unwrap();
+        let mut child = a.crossover(b, rng);
+        // the parents can differ in cells that were locked after they were bred
+        for y in 0..child.height() {
+            for x in 0..child.width() {
+                if is_locked(x, y) {
+                    *child.get_mut(x, y).unwrap() = a.get(x, y).unwrap();
+                }
+            }
+        }
+        child.mutate(rng, mutation_amount, mutation_rate, |x, y| !is_locked(x, y));
+        population.push(child);
+    }
+    population
+}
+
+/// `codes` are the ones in the editor, used to start a population, and `is_locked` tells which of their cells are locked.
+pub fn draw(
+    ui: &mut egui::Ui,
+    evolution: &mut Evolution,
+    worker: &mut Worker,
+    codes: &Codes,
+    mode: LoopMode,
+    is_locked: impl Fn(usize, usize) -> bool,
+) -> Option<Event> {
+    let mut event = None;
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::DragValue::new(&mut evolution.population)
+                .range(2..=MAX_POPULATION)
+                .prefix("population "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut evolution.mutation_amount)
+                .range(1..=crate::codes::MAX_CODE)
+                .prefix("±"),
+        )
+        .on_hover_text("largest change of a code when mutating");
+        ui.add(
+            egui::DragValue::new(&mut evolution.mutation_rate)
+                .range(0.0..=1.0)
+                .speed(0.01)
+                .prefix("p "),
+        )
+        .on_hover_text("probability of each code changing when mutating");
+        ui.label("seed");
+        ui.add(egui::DragValue::new(&mut evolution.seed));
+    });
+    ui.horizontal(|ui| {
+        if ui
+            .button("start from editor")
+            .on_hover_text(
+                "replace the population with the codes in the editor and mutations of them",
+            )
+            .clicked()
+        {
+            evolution.seed_population(codes, &is_locked);
+            evolution.request_decodes(worker, mode);
+        }
+        let picked = evolution.candidates.iter().any(|c| c.picked);
+        if ui
+            .add_enabled(picked, egui::Button::new("next generation"))
+            .on_hover_text("keep the picked candidates and breed new ones from them")
+            .clicked()
+        {
+            evolution.next_generation(&is_locked);
+            evolution.request_decodes(worker, mode);
+        }
+        if !evolution.candidates.is_empty() {
+            ui.label(format!("generation {}", evolution.generation));
+        }
+    });
+    egui::ScrollArea::vertical()
+        .max_height(500.0)
+        .show(ui, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for (i, candidate) in evolution.candidates.iter_mut().enumerate() {
+                    ui.group(|ui| {
+                        ui.vertical(|ui| {
+                            ui.label(format!("#{i}"));
+                            match &candidate.samples {
+                                Some(Ok(samples)) => {
+                                    if ui
+                                        .selectable_label(evolution.playing == Some(i), "▶")
+                                        .on_hover_text("audition")
+                                        .clicked()
+                                    {
+                                        evolution.playing = Some(i);
+                                        event = Some(Event::Audition(samples.clone()));
+                                    }
+                                }
+                                Some(Err(e)) => {
+                                    ui.label("⚠")
+                                        .on_hover_text(format!("unable to decode: {e}"));
+                                }
+                                None => {
+                                    ui.add(egui::Spinner::new());
+                                }
+                            }
+                            ui.checkbox(&mut candidate.picked, "pick");
+                            if ui
+                                .button("edit")
+                                .on_hover_text("open in the editor")
+                                .clicked()
+                            {
+                                event = Some(Event::Edit(candidate.codes.clone()));
+                            }
+                        });
+                    });
+                }
+            });
+        });
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codes::{tests::is_locked, MAX_CODE};
+
+    use super::*;
+
+    /// Random parents that agree on the locked cells.
+    fn parents(seed: &mut u64) -> Vec<Codes> {
+        let mut base = Codes::new();
+        base.reshape(12, 4);
+        base.randomize(&mut next_rng(seed), |_, _| true);
+        (0..3)
+            .map(|_| {
+                let mut parent = base.clone();
+                parent.randomize(&mut next_rng(seed), |x, y| !is_locked(x, y));
+                parent
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossovers_come_from_the_parents() {
+        let mut seed = 0;
+        let parents = parents(&mut seed);
+        let population = breed(&parents, 8, &mut next_rng(&mut seed), 0, 0.0, is_locked);
+        assert_eq!(population.len(), 8);
+        assert_eq!(population[..parents.len()], parents);
+        for child in &population {
+            assert_eq!((child.width(), child.height()), (12, 4));
+            for y in 0..child.height() {
+                for x in 0..child.width() {
+                    let code = child.get(x, y);
+                    assert!(parents.iter().any(|p| p.get(x, y) == code));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn breeding_is_reproducible_and_keeps_locked_cells() {
+        let mut seed = 0;
+        let parents = parents(&mut seed);
+        let population = |mut seed: u64| {
+            breed(
+                &parents,
+                8,
+                &mut next_rng(&mut seed),
+                MAX_CODE,
+                1.0,
+                is_locked,
+            )
+        };
+        assert_eq!(population(1), population(1));
+        assert_ne!(population(1), population(2));
+        for child in population(1) {
+            assert_eq!((child.width(), child.height()), (12, 4));
+            for y in 0..child.height() {
+                assert_eq!(child.get(y, y), parents[0].get(y, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+#[cfg(target_arch = "wasm32")]
+use {crate::compute::into_jserr, js_sys::wasm_bindgen::JsCast as _};
+
+/// Save a file. Natively it is written relative to the working directory, on the web it is downloaded by the browser.
+pub fn save(name: &str, mime_type: &str, data: &[u8]) -> anyhow::Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = mime_type;
+        std::fs::write(name, data)?;
+        log::info!("saved {:?}", std::fs::canonicalize(name)?);
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type(mime_type);
+        let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+            &js_sys::Array::of1(&js_sys::Uint8Array::from(data)),
+            &options,
+        )
+        .map_err(into_jserr)?;
+        let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(into_jserr)?;
+        let anchor = web_sys::window()
+            .and_then(|w| w.document())
+            .ok_or_else(|| anyhow::anyhow!("no document"))?
+            .create_element("a")
+            .map_err(into_jserr)?
+            .dyn_into::<web_sys::HtmlAnchorElement>()
+            .map_err(|_| anyhow::anyhow!("unable to create link element"))?;
+        anchor.set_href(&url);
+        anchor.set_download(name);
+        anchor.click();
+        web_sys::Url::revoke_object_url(&url).map_err(into_jserr)?;
+    }
+    Ok(())
+}
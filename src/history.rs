@@ -0,0 +1,104 @@
+use crate::codes::Codes;
+
+const MAX_UNDO_STEPS: usize = 1000;
+
+/// Undo/redo stacks of code snapshots.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Codes>,
+    redo: Vec<Codes>,
+    /// Set while the pointer is held down, so that a whole slider drag becomes a single undo step.
+    dragging: bool,
+}
+
+impl History {
+    /// Record that `previous` has been replaced by an edit.
+    pub fn record(&mut self, previous: Codes, dragging: bool) {
+        if !(dragging && self.dragging) {
+            self.undo.push(previous);
+            if self.undo.len() > MAX_UNDO_STEPS {
+                self.undo.remove(0);
+            }
+        }
+        self.dragging = dragging;
+        self.redo.clear();
+    }
+
+    /// Call when the pointer is released to end the current drag.
+    pub fn end_drag(&mut self) {
+        self.dragging = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Returns the codes to go back to, if any.
+    pub fn undo(&mut self, current: Codes) -> Option<Codes> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current);
+        self.dragging = false;
+        Some(previous)
+    }
+
+    pub fn redo(&mut self, current: Codes) -> Option<Codes> {
+        let next = self.redo.pop()?;
+        self.undo.push(current);
+        self.dragging = false;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(code: u32) -> Codes {
+        Codes::from_rows(&[vec![code]]).unwrap()
+    }
+
+    #[test]
+    fn drag_is_one_step() {
+        let mut history = History::default();
+        history.record(codes(0), true);
+        history.record(codes(1), true);
+        history.record(codes(2), true);
+        history.end_drag();
+        history.record(codes(3), false);
+        assert_eq!(history.undo(codes(4)), Some(codes(3)));
+        assert_eq!(history.undo(codes(3)), Some(codes(0)));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn edit_clears_redo() {
+        let mut history = History::default();
+        history.record(codes(0), false);
+        assert_eq!(history.undo(codes(1)), Some(codes(0)));
+        assert!(history.can_redo());
+        history.record(codes(0), false);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(codes(2)), None);
+    }
+
+    #[test]
+    fn undo_is_limited() {
+        let mut history = History::default();
+        for code in 0..MAX_UNDO_STEPS as u32 + 10 {
+            history.record(codes(code), false);
+        }
+        let mut current = codes(0);
+        let mut steps = 0;
+        while let Some(previous) = history.undo(current.clone()) {
+            current = previous;
+            steps += 1;
+        }
+        assert_eq!(steps, MAX_UNDO_STEPS);
+        // the oldest steps are the ones dropped
+        assert_eq!(current, codes(10));
+    }
+}
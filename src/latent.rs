@@ -0,0 +1,199 @@
+//! Editing the continuous latent that the decoder sees, rather than the codes that are quantized to it.
+//!
+//! Each frame is the sum of the codebook embeddings of its codes. It can be edited per dimension,
+//! or along the principal axes of the latents of random codes.
+
+use candle_core::{Device, Tensor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    code_ui,
+    codes::Codes,
+    compute::{LatentStats, LoopMode},
+    worker::{Request, Worker},
+};
+
+/// Number of principal axes to edit along.
+pub const AXES: usize = 16;
+/// Range of the sliders, in standard deviations. Latents of actual codes can be outside of it, and are kept as is.
+const SLIDER_RANGE: f32 = 4.0;
+const COLUMN_WIDTH: f32 = 300.0;
+
+/// A `(dimension, frames)` latent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Latent {
+    values: Vec<f32>,
+    frames: usize,
+}
+
+impl Latent {
+    pub fn to_tensor(&self, device: &Device) -> anyhow::Result<Tensor> {
+        Ok(Tensor::from_vec(
+            self.values.clone(),
+            (self.dimension(), self.frames),
+            device,
+        )?)
+    }
+
+    pub fn from_tensor(tensor: &Tensor) -> anyhow::Result<Self> {
+        let (dimension, frames) = tensor.dims2()?;
+        anyhow::ensure!(dimension > 0 && frames > 0, "empty latent");
+        Ok(Self {
+            values: tensor.flatten_all()?.to_vec1()?,
+            frames,
+        })
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.values.len() / self.frames
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn get(&self, d: usize, x: usize) -> f32 {
+        self.values[d * self.frames + x]
+    }
+
+    fn get_mut(&mut self, d: usize, x: usize) -> &mut f32 {
+        &mut self.values[d * self.frames + x]
+    }
+
+    /// Position of frame `x` along `axis`, relative to `mean`.
+    fn project(&self, x: usize, mean: &[f32], axis: &[f32]) -> f32 {
+        (0..self.dimension())
+            .map(|d| (self.get(d, x) - mean[d]) * axis[d])
+            .sum()
+    }
+
+    /// Move frame `x` by `amount` along `axis`.
+    fn translate(&mut self, x: usize, axis: &[f32], amount: f32) {
+        for (d, a) in axis.iter().enumerate() {
+            *self.get_mut(d, x) += a * amount;
+        }
+    }
+}
+
+pub struct LatentEditor {
+    latent: Option<Latent>,
+    /// Requested from the worker when it is started.
+    stats: Option<LatentStats>,
+    /// Edit along the principal axes rather than the dimensions.
+    edit_axes: bool,
+    /// Decoded samples of the latent.
+    samples: Option<Vec<f32>>,
+    /// What the samples are, or are being, decoded from.
+    decoding: Option<(Latent, LoopMode)>,
+}
+
+impl Default for LatentEditor {
+    fn default() -> Self {
+        Self {
+            latent: None,
+            stats: None,
+            edit_axes: true,
+            samples: None,
+            decoding: None,
+        }
+    }
+}
+
+impl LatentEditor {
+    pub fn set_stats(&mut self, stats: LatentStats) {
+        self.stats = Some(stats);
+    }
+
+    pub fn set_latent(&mut self, latent: Latent) {
+        self.latent = Some(latent);
+    }
+
+    /// Returns true if the samples are for the current latent, and so should be played.
+    pub fn set_samples(&mut self, latent: &Latent, mode: LoopMode, samples: Vec<f32>) -> bool {
+        if self.latent.as_ref() == Some(latent)
+            && self.decoding.as_ref().is_some_and(|(_, m)| *m == mode)
+        {
+            self.samples = Some(samples);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn samples(&self) -> Option<&[f32]> {
+        self.samples.as_deref()
+    }
+}
+
+/// `codes` are the ones in the editor, that can be converted to a latent.
+pub fn draw(
+    ui: &mut egui::Ui,
+    editor: &mut LatentEditor,
+    worker: &mut Worker,
+    codes: &Codes,
+    mode: LoopMode,
+) {
+    ui.horizontal(|ui| {
+        if ui
+            .button("from editor")
+            .on_hover_text("replace the latent with the one of the codes in the editor")
+            .clicked()
+        {
+            worker.request(Request::Latent(codes.clone()));
+        }
+        ui.separator();
+        ui.add_enabled_ui(editor.stats.is_some(), |ui| {
+            ui.radio_value(&mut editor.edit_axes, true, "principal axes");
+            ui.radio_value(&mut editor.edit_axes, false, "dimensions");
+        });
+    });
+    let (Some(latent), Some(stats)) = (&mut editor.latent, &editor.stats) else {
+        ui.label("convert the codes in the editor to start editing their latent");
+        return;
+    };
+    let rows = if editor.edit_axes {
+        stats.axes.len()
+    } else {
+        latent.dimension()
+    };
+    code_ui::draw_columns(ui, latent.frames(), rows, COLUMN_WIDTH, |ui, x| {
+        for row in 0..rows {
+            ui.horizontal(|ui| {
+                if editor.edit_axes {
+                    // in standard deviations from the mean
+                    let axis = &stats.axes[row];
+                    let std = stats.axis_std[row].max(f32::EPSILON);
+                    let position = latent.project(x, &stats.mean, axis) / std;
+                    let mut value = position;
+                    ui.weak(format!("pc{row}"));
+                    ui.add(
+                        egui::Slider::new(&mut value, -SLIDER_RANGE..=SLIDER_RANGE)
+                            .clamping(egui::SliderClamping::Edits)
+                            .suffix(" σ"),
+                    );
+                    if value != position {
+                        latent.translate(x, axis, (value - position) * std);
+                    }
+                } else {
+                    let (mean, std) = (stats.mean[row], stats.std[row]);
+                    ui.weak(format!("d{row}"));
+                    ui.add(
+                        egui::Slider::new(
+                            latent.get_mut(row, x),
+                            mean - SLIDER_RANGE * std..=mean + SLIDER_RANGE * std,
+                        )
+                        .clamping(egui::SliderClamping::Edits),
+                    );
+                }
+            });
+        }
+    });
+    let latent = editor.latent.as_ref().unwrap();
+    if editor.decoding.as_ref().map(|(l, m)| (l, *m)) != Some((latent, mode)) {
+        worker.request(Request::DecodeLatent {
+            latent: latent.clone(),
+            mode,
+        });
+        editor.decoding = Some((latent.clone(), mode));
+    }
+}
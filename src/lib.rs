@@ -1,8 +1,46 @@
 #![warn(clippy::all, rust_2018_idioms)]
+//! Explore the decoder of meta's encodec model by generating looping sounds.
+//!
+//! Besides the app, which needs the default `gui` feature, this can be used as a library:
+//! [`codes::Codes`] holds a grid of codes, [`compute::Compute`] decodes it into a loop,
+//! [`synth::SamplePlayer`] plays the loop and, with the `audio` feature, `audio::AudioManager` sends it to an output device.
+//! [`patch`] and [`wav`] read and write files, and [`render`] does all of it without an audio device.
 
+#[cfg(feature = "gui")]
 mod app;
+#[cfg(feature = "gui")]
 pub use app::EncodecExplorer;
-mod audio;
+#[cfg(feature = "audio")]
+pub mod audio;
+#[cfg(feature = "gui")]
+mod audio_ui;
+#[cfg(feature = "gui")]
 mod code_ui;
-mod compute;
-mod synth;
+pub mod codes;
+pub mod compute;
+#[cfg(feature = "gui")]
+mod contribution_ui;
+#[cfg(feature = "gui")]
+mod evolution;
+#[cfg(feature = "gui")]
+mod files;
+#[cfg(feature = "gui")]
+mod history;
+#[cfg(feature = "gui")]
+mod latent;
+pub mod patch;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod render;
+pub mod resample;
+#[cfg(any(feature = "gui", not(target_arch = "wasm32")))]
+mod share;
+pub mod spectrum;
+#[cfg(feature = "gui")]
+mod spectrum_ui;
+pub mod synth;
+pub mod wav;
+#[cfg(feature = "gui")]
+mod worker;
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
+#[doc(hidden)]
+pub use worker::web_worker_main;
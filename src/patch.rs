@@ -0,0 +1,387 @@
+//! Saved code grids ("patches").
+//!
+//! There are two variants of the format, both storing the same fields.
+//!
+//! JSON:
+//! ```json
+//! {
+//!   "version": 1,
+//!   "model": "facebook/encodec_24khz",
+//!   "codebooks": 2,
+//!   "frames": 3,
+//!   "codes": [[1, 2, 3], [4, 5, 6]]
+//! }
+//! ```
+//! `codes` has one row per codebook, each containing one code per frame.
+//!
+//! Binary, with all integers little endian:
+//! - magic `b"ENCX"`
+//! - `u16` version
+//! - `u8` length of the model id, followed by the model id as utf-8
+//! - `u16` number of codebooks
+//! - `u16` number of frames
+//! - `u16` codes, one row per codebook like in the JSON variant
+//!
+//! Packed, used for sharing through urls:
+//! - `u8` version
+//! - `u8` number of codebooks
+//! - `u16` number of frames, little endian
+//! - codes as a stream of 10-bit values, least significant bit first, in the same order as above
+//!
+//! The model is implicitly [`compute::MODEL_ID`]. The packed variant is base64url encoded without padding.
+//!
+//! Codes must be in `0..=1023`, and the grid can have at most [`codes::MAX_LAYERS`] codebooks and [`codes::MAX_FRAGMENTS`] frames.
+
+use anyhow::{bail, ensure, Context as _};
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codes::{self, Codes},
+    compute,
+};
+
+pub const VERSION: u16 = 1;
+const MAGIC: &[u8; 4] = b"ENCX";
+const CODE_BITS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Json,
+    Binary,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Binary => "encx",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Binary => "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Patch {
+    version: u16,
+    model: String,
+    codebooks: usize,
+    frames: usize,
+    codes: Vec<Vec<u32>>,
+}
+
+impl Patch {
+    fn from_codes(codes: &Codes) -> Self {
+        Self {
+            version: VERSION,
+            model: compute::MODEL_ID.to_string(),
+            codebooks: codes.height(),
+            frames: codes.width(),
+            codes: codes.rows().map(<[u32]>::to_vec).collect(),
+        }
+    }
+
+    fn into_codes(self) -> anyhow::Result<Codes> {
+        ensure!(
+            self.version == VERSION,
+            "unsupported version {}, expected {VERSION}",
+            self.version
+        );
+        ensure!(
+            self.model == compute::MODEL_ID,
+            "patch is for model {:?}, expected {:?}",
+            self.model,
+            compute::MODEL_ID
+        );
+        ensure!(
+            (1..=codes::MAX_LAYERS).contains(&self.codebooks),
+            "number of codebooks must be in 1..={}, got {}",
+            codes::MAX_LAYERS,
+            self.codebooks
+        );
+        ensure!(
+            (1..=codes::MAX_FRAGMENTS).contains(&self.frames),
+            "number of frames must be in 1..={}, got {}",
+            codes::MAX_FRAGMENTS,
+            self.frames
+        );
+        ensure!(
+            self.codes.len() == self.codebooks,
+            "expected {} codebooks, got {}",
+            self.codebooks,
+            self.codes.len()
+        );
+        for (i, row) in self.codes.iter().enumerate() {
+            ensure!(
+                row.len() == self.frames,
+                "expected {} frames in codebook {i}, got {}",
+                self.frames,
+                row.len()
+            );
+            if let Some(code) = row.iter().find(|&&c| c > codes::MAX_CODE) {
+                bail!(
+                    "code {code} in codebook {i} is out of range 0..={}",
+                    codes::MAX_CODE
+                );
+            }
+        }
+        Codes::from_rows(&self.codes)
+    }
+}
+
+/// Validate codebook rows of codes, as if they were loaded from a patch.
+pub fn from_rows(rows: Vec<Vec<u32>>) -> anyhow::Result<Codes> {
+    Patch {
+        version: VERSION,
+        model: compute::MODEL_ID.to_string(),
+        codebooks: rows.len(),
+        frames: rows.first().map_or(0, Vec::len),
+        codes: rows,
+    }
+    .into_codes()
+}
+
+pub fn save(codes: &Codes, format: Format) -> anyhow::Result<Vec<u8>> {
+    let patch = Patch::from_codes(codes);
+    Ok(match format {
+        Format::Json => serde_json::to_vec_pretty(&patch)?,
+        Format::Binary => {
+            let mut data = MAGIC.to_vec();
+            data.extend(patch.version.to_le_bytes());
+            data.push(u8::try_from(patch.model.len())?);
+            data.extend(patch.model.as_bytes());
+            data.extend(u16::try_from(patch.codebooks)?.to_le_bytes());
+            data.extend(u16::try_from(patch.frames)?.to_le_bytes());
+            for code in patch.codes.iter().flatten() {
+                data.extend(u16::try_from(*code)?.to_le_bytes());
+            }
+            data
+        }
+    })
+}
+
+/// Load either format, detected by the magic of the binary variant.
+pub fn load(data: &[u8]) -> anyhow::Result<Codes> {
+    let patch = if let Some(data) = data.strip_prefix(MAGIC) {
+        read_binary(data).context("invalid binary patch")?
+    } else {
+        serde_json::from_slice(data).context("invalid json patch")?
+    };
+    patch.into_codes()
+}
+
+/// Pack into a compact base64url string.
+pub fn to_packed(codes: &Codes) -> anyhow::Result<String> {
+    let mut data = vec![u8::try_from(VERSION)?, u8::try_from(codes.height())?];
+    data.extend(u16::try_from(codes.width())?.to_le_bytes());
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &code in codes.rows().flatten() {
+        ensure!(code <= codes::MAX_CODE, "code {code} out of range");
+        acc |= code << bits;
+        bits += CODE_BITS;
+        while bits >= 8 {
+            data.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        data.push(acc as u8);
+    }
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data))
+}
+
+pub fn from_packed(packed: &str) -> anyhow::Result<Codes> {
+    let data = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(packed)?;
+    let mut reader = Reader(&data);
+    let version = u16::from(reader.u8()?);
+    let codebooks = usize::from(reader.u8()?);
+    let frames = usize::from(reader.u16()?);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut next_code = || -> anyhow::Result<u32> {
+        while bits < CODE_BITS {
+            acc |= u32::from(reader.u8()?) << bits;
+            bits += 8;
+        }
+        let code = acc & ((1 << CODE_BITS) - 1);
+        acc >>= CODE_BITS;
+        bits -= CODE_BITS;
+        Ok(code)
+    };
+    let codes = (0..codebooks)
+        .map(|_| (0..frames).map(|_| next_code()).collect())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    ensure!(reader.0.is_empty(), "{} trailing bytes", reader.0.len());
+    Patch {
+        version,
+        model: compute::MODEL_ID.to_string(),
+        codebooks,
+        frames,
+        codes,
+    }
+    .into_codes()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        ensure!(self.0.len() >= n, "unexpected end of file");
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+}
+
+fn read_binary(data: &[u8]) -> anyhow::Result<Patch> {
+    let mut reader = Reader(data);
+    let version = reader.u16()?;
+    ensure!(version == VERSION, "unsupported version {version}");
+    let model_len = usize::from(reader.u8()?);
+    let model = String::from_utf8(reader.take(model_len)?.to_vec())?;
+    let codebooks = usize::from(reader.u16()?);
+    let frames = usize::from(reader.u16()?);
+    let codes = (0..codebooks)
+        .map(|_| {
+            (0..frames)
+                .map(|_| Ok(u32::from(reader.u16()?)))
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    ensure!(reader.0.is_empty(), "{} trailing bytes", reader.0.len());
+    Ok(Patch {
+        version,
+        model,
+        codebooks,
+        frames,
+        codes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Codes {
+        Codes::from_rows(&[vec![0, 1, 2], vec![1023, 512, 7]]).unwrap()
+    }
+
+    fn json(version: u16, model: &str, codes: &[&[u32]]) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "version": version,
+            "model": model,
+            "codebooks": codes.len(),
+            "frames": codes[0].len(),
+            "codes": codes,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [Format::Json, Format::Binary] {
+            let data = save(&example(), format).unwrap();
+            assert_eq!(load(&data).unwrap(), example(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn reads_json() {
+        let data = json(VERSION, compute::MODEL_ID, &[&[1, 2, 3], &[4, 5, 6]]);
+        let codes = load(&data).unwrap();
+        assert_eq!(codes.rows().collect::<Vec<_>>(), [[1, 2, 3], [4, 5, 6]]);
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        let model = compute::MODEL_ID;
+        for data in [
+            json(VERSION, model, &[&[1, 2, 1024], &[4, 5, 6]]),
+            json(VERSION, model, &[&[1, 2, 3], &[4, 5]]),
+            json(VERSION + 1, model, &[&[1, 2, 3], &[4, 5, 6]]),
+            json(VERSION, "facebook/encodec_48khz", &[&[1, 2, 3], &[4, 5, 6]]),
+        ] {
+            assert!(load(&data).is_err(), "{}", String::from_utf8_lossy(&data));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_binary() {
+        let data = save(&example(), Format::Binary).unwrap();
+        let header = MAGIC.len() + 2 + 1 + compute::MODEL_ID.len() + 4;
+        let mut out_of_range = data.clone();
+        out_of_range[header..header + 2].copy_from_slice(&1024u16.to_le_bytes());
+        let mut version = data.clone();
+        version[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let mut model = data.clone();
+        model[MAGIC.len() + 3] = b'x';
+        let mut magic = data.clone();
+        magic[3] = b'Y';
+        let truncated = data[..data.len() - 1].to_vec();
+        let mut trailing = data.clone();
+        trailing.push(0);
+        for (name, data) in [
+            ("out of range", out_of_range),
+            ("version", version),
+            ("model", model),
+            ("magic", magic),
+            ("truncated", truncated),
+            ("trailing", trailing),
+        ] {
+            assert!(load(&data).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn packed_round_trip() {
+        // 3 cells don't fill whole bytes
+        let odd = Codes::from_rows(&[vec![1, 1023, 5]]).unwrap();
+        let mut max = Codes::new();
+        max.reshape(codes::MAX_FRAGMENTS, codes::MAX_LAYERS);
+        for row in 0..max.height() {
+            for column in 0..max.width() {
+                *max.get_mut(column, row).unwrap() = codes::MAX_CODE;
+            }
+        }
+        for codes in [odd, example(), max] {
+            assert_eq!(from_packed(&to_packed(&codes).unwrap()).unwrap(), codes);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_packed() {
+        let packed = to_packed(&example()).unwrap();
+        let base64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let data = base64.decode(&packed).unwrap();
+        let truncated = base64.encode(&data[..data.len() - 1]);
+        let mut trailing = data.clone();
+        trailing.push(0);
+        let trailing = base64.encode(trailing);
+        for packed in [
+            truncated.as_str(),
+            trailing.as_str(),
+            &packed[..packed.len() - 1],
+            "not base64!",
+            "",
+        ] {
+            assert!(from_packed(packed).is_err(), "{packed:?}");
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Rendering codes to wav files without the gui or an audio device, for the `encodec-explorer-render` binary.
+
+use anyhow::Context as _;
+
+use crate::{
+    codes::Codes,
+    compute::{self, Compute, LoopMode},
+    patch, resample, share, wav,
+};
+
+/// Ten minutes, which keeps the output well within what a wav file can hold.
+pub const MAX_DURATION: f32 = 600.0;
+pub const MAX_SAMPLE_RATE: u32 = 192000;
+
+pub enum Input {
+    /// Contents of a saved patch.
+    Patch(Vec<u8>),
+    /// Codebook rows separated by `;`, each with comma separated codes. Or a shared link.
+    Inline(String),
+}
+
+pub struct Options {
+    /// Length of the output in seconds, one loop if `None`.
+    pub duration: Option<f32>,
+    pub sample_rate: u32,
+    pub float: bool,
+    /// Frames to warm up the decoder with, or `None` to decode by blending two periods.
+    pub warmup: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            duration: None,
+            sample_rate: compute::SAMPLE_RATE,
+            float: false,
+            warmup: Some(compute::DEFAULT_WARMUP_FRAMES),
+        }
+    }
+}
+
+impl Options {
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (1..=MAX_SAMPLE_RATE).contains(&self.sample_rate),
+            "sample rate must be in 1..={MAX_SAMPLE_RATE}"
+        );
+        if let Some(duration) = self.duration {
+            anyhow::ensure!(
+                (0.0..=MAX_DURATION).contains(&duration),
+                "duration must be in 0..={MAX_DURATION} seconds, got {duration}"
+            );
+        }
+        if let Some(warmup) = self.warmup {
+            anyhow::ensure!(
+                warmup <= compute::MAX_WARMUP_FRAMES,
+                "warm-up must be at most {} frames, got {warmup}",
+                compute::MAX_WARMUP_FRAMES
+            );
+        }
+        Ok(())
+    }
+}
+
+fn parse_inline(text: &str) -> anyhow::Result<Codes> {
+    if text.contains('#') {
+        return share::codes_from_link(text).context("invalid link");
+    }
+    let rows = text
+        .split(';')
+        .map(|row| {
+            row.split(',')
+                .map(|code| {
+                    code.trim()
+                        .parse::<u32>()
+                        .with_context(|| format!("invalid code {code:?}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    patch::from_rows(rows)
+}
+
+/// Decode the codes and loop them, returning the contents of a wav file.
+pub fn render(input: Input, options: &Options) -> anyhow::Result<Vec<u8>> {
+    options.validate()?;
+    let codes = match input {
+        Input::Patch(data) => patch::load(&data)?,
+        Input::Inline(text) => parse_inline(&text)?,
+    };
+    let compute = pollster::block_on(Compute::new())?;
+    let mode = match options.warmup {
+        Some(frames) => LoopMode::WarmUp(frames),
+        None => LoopMode::Blend,
+    };
+    let samples = compute.decode_codes(&codes.to_tensor(compute.device())?, mode)?;
+    // resample a single period, so it still loops seamlessly
+    let samples = resample::resample(&samples, compute::SAMPLE_RATE, options.sample_rate, true);
+    let length = match options.duration {
+        Some(duration) => (duration * options.sample_rate as f32).round() as usize,
+        None => samples.len(),
+    };
+    let samples: Vec<f32> = samples.iter().copied().cycle().take(length).collect();
+    let format = if options.float {
+        wav::Format::Float32
+    } else {
+        wav::Format::Int16
+    };
+    wav::write(&samples, options.sample_rate, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inline_codes() {
+        let codes = parse_inline("1, 2,3;4,5 ,1023").unwrap();
+        assert_eq!(codes.rows().collect::<Vec<_>>(), [[1, 2, 3], [4, 5, 1023]]);
+        let codes = parse_inline("7").unwrap();
+        assert_eq!(codes.rows().collect::<Vec<_>>(), [[7]]);
+    }
+
+    #[test]
+    fn rejects_invalid_inline_codes() {
+        for text in ["1,2,3;4,5", "1,2,1024", "1,-2", "1,x", "1,,2", "", ";"] {
+            assert!(parse_inline(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(Options::default().validate().is_ok());
+        let with_duration = |duration| Options {
+            duration: Some(duration),
+            ..Options::default()
+        };
+        assert!(with_duration(MAX_DURATION).validate().is_ok());
+        for duration in [-1.0, f32::INFINITY, f32::NAN, MAX_DURATION + 1.0, 1e30] {
+            assert!(with_duration(duration).validate().is_err(), "{duration}");
+        }
+        for sample_rate in [0, MAX_SAMPLE_RATE + 1] {
+            let options = Options {
+                sample_rate,
+                ..Options::default()
+            };
+            assert!(options.validate().is_err(), "{sample_rate}");
+        }
+        let options = Options {
+            warmup: Some(compute::MAX_WARMUP_FRAMES + 1),
+            ..Options::default()
+        };
+        assert!(options.validate().is_err());
+    }
+}
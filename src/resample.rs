@@ -0,0 +1,173 @@
+//! Band-limited resampling using a windowed sinc kernel.
+
+use std::f64::consts::PI;
+
+/// Half width of the kernel, in zero crossings of the sinc.
+const ZERO_CROSSINGS: f64 = 16.0;
+/// Cutoff relative to the lower of the two nyquist frequencies, to leave some room for the transition band.
+const CUTOFF: f64 = 0.95;
+
+/// Kernel values are tabulated at this many fractional offsets per input sample, and interpolated in between.
+const PHASES: usize = 512;
+
+/// Resample `input` from `from_rate` to `to_rate`.
+///
+/// If `looped` is set the input is treated as one period of a looping signal, and the output is exactly one period as well.
+/// This changes the rate very slightly, since the number of output samples has to be an integer.
+pub fn resample(input: &[f32], from_rate: u32, to_rate: u32, looped: bool) -> Vec<f32> {
+    if from_rate == to_rate || input.is_empty() {
+        return input.to_vec();
+    }
+    let ratio = to_rate as f64 / from_rate as f64;
+    let output_size = ((input.len() as f64 * ratio).round() as usize).max(1);
+    let step = if looped {
+        input.len() as f64 / output_size as f64
+    } else {
+        1.0 / ratio
+    };
+    // relative to the input nyquist frequency
+    let cutoff = CUTOFF * ratio.min(1.0);
+    // in input samples
+    let half_width = ZERO_CROSSINGS / cutoff;
+    let taps = half_width.ceil() as usize;
+    let kernel = Kernel::new(cutoff, half_width, taps);
+    // pad the input with what comes before and after it, so each output sample is a plain dot product
+    let len = input.len() as i64;
+    let padded: Vec<f32> = (-(taps as i64)..len + taps as i64)
+        .map(|j| {
+            if looped {
+                input[j.rem_euclid(len) as usize]
+            } else if (0..len).contains(&j) {
+                input[j as usize]
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    (0..output_size)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let base = pos.floor();
+            // the input samples from base + 1 - taps to base + taps
+            let window = &padded[base as usize + 1..][..2 * taps];
+            kernel.apply(pos - base, window)
+        })
+        .collect()
+}
+
+/// The windowed sinc at offsets `k - phase / PHASES` for `k` in `1 - taps..=taps`, for each phase in `0..=PHASES`.
+struct Kernel {
+    taps: usize,
+    table: Vec<f32>,
+}
+
+impl Kernel {
+    fn new(cutoff: f64, half_width: f64, taps: usize) -> Self {
+        let table = (0..=PHASES)
+            .flat_map(|phase| {
+                (1 - taps as i64..=taps as i64).map(move |k| {
+                    let x = k as f64 - phase as f64 / PHASES as f64;
+                    if x.abs() > half_width {
+                        0.0
+                    } else {
+                        (cutoff * sinc(x * cutoff) * blackman(x / half_width)) as f32
+                    }
+                })
+            })
+            .collect();
+        Self { taps, table }
+    }
+
+    /// Interpolate `window` at `fraction` of a sample past its center.
+    fn apply(&self, fraction: f64, window: &[f32]) -> f32 {
+        let phase = fraction * PHASES as f64;
+        let index = (phase as usize).min(PHASES - 1);
+        let weight = (phase - index as f64) as f32;
+        let row = |phase: usize| &self.table[phase * 2 * self.taps..][..2 * self.taps];
+        let dot = |row: &[f32]| row.iter().zip(window).map(|(k, s)| k * s).sum::<f32>();
+        let (a, b) = (dot(row(index)), dot(row(index + 1)));
+        a + (b - a) * weight
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `-1..=1`.
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `cycles` periods of a sine, `len` samples long.
+    fn sine(cycles: usize, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * (cycles * i) as f64 / len as f64).sin() as f32)
+            .collect()
+    }
+
+    /// Magnitude of each bin in dB relative to the largest one.
+    fn spectrum(samples: &[f32]) -> Vec<f32> {
+        let fft = realfft::RealFftPlanner::<f32>::new().plan_fft_forward(samples.len());
+        let mut input = samples.to_vec();
+        let mut output = fft.make_output_vec();
+        fft.process(&mut input, &mut output).unwrap();
+        let magnitudes: Vec<f32> = output.iter().map(|c| c.norm()).collect();
+        let max = magnitudes.iter().copied().fold(0.0, f32::max);
+        magnitudes
+            .into_iter()
+            .map(|m| 20.0 * (m / max).max(1e-9).log10())
+            .collect()
+    }
+
+    #[test]
+    fn resampled_sine_has_no_images() {
+        // 0.1 s, so a looped sine with a whole number of periods doesn't leak into other bins
+        let len = 2400;
+        for to_rate in [48000, 44100] {
+            for frequency in [1000, 5000] {
+                let cycles = frequency * len / 24000;
+                let output = resample(&sine(cycles, len), 24000, to_rate, true);
+                let spectrum = spectrum(&output);
+                let (peak, _) = spectrum
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .unwrap();
+                assert_eq!(peak, cycles, "{frequency} Hz to {to_rate} Hz");
+                for (bin, &db) in spectrum.iter().enumerate() {
+                    if bin != peak {
+                        assert!(
+                            db < -60.0,
+                            "{frequency} Hz to {to_rate} Hz: bin {bin} at {db} dB"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn looped_output_wraps_smoothly() {
+        let (len, cycles) = (2400, 100);
+        for to_rate in [48000, 44100] {
+            let output = resample(&sine(cycles, len), 24000, to_rate, true);
+            assert_eq!(output.len(), len * to_rate as usize / 24000);
+            // the samples around the wrap are those of the sine at the new rate
+            let expected = sine(cycles, output.len());
+            let n = output.len();
+            for i in (0..32).chain(n - 32..n) {
+                let error = (output[i] - expected[i]).abs();
+                assert!(error < 1e-3, "{to_rate} Hz: sample {i} is off by {error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Sharing code grids through the url fragment of the web build, as `#codes=<packed>`.
+//! Other `&` separated parameters in the fragment, such as `dev`, are kept as is.
+
+use crate::{codes::Codes, patch};
+
+const CODES_PARAM: &str = "codes=";
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
+const WEB_URL: &str = "https://jnises.github.io/encodec-explorer/";
+
+/// Read codes from the url, if there are any.
+#[cfg(feature = "gui")]
+pub fn read_codes() -> Option<Codes> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let hash = web_sys::window()?.location().hash().ok()?;
+        let packed = hash
+            .trim_start_matches('#')
+            .split('&')
+            .find_map(|param| param.strip_prefix(CODES_PARAM))?;
+        match patch::from_packed(packed) {
+            Ok(codes) => Some(codes),
+            Err(e) => {
+                log::warn!("invalid codes in url: {e:?}");
+                None
+            }
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    None
+}
+
+/// Read the codes of a link created by [`link`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn codes_from_link(link: &str) -> anyhow::Result<Codes> {
+    let (_, fragment) = link
+        .split_once('#')
+        .ok_or_else(|| anyhow::anyhow!("no fragment in link"))?;
+    let packed = fragment
+        .split('&')
+        .find_map(|param| param.strip_prefix(CODES_PARAM))
+        .ok_or_else(|| anyhow::anyhow!("no codes in link"))?;
+    patch::from_packed(packed)
+}
+
+/// Replace the codes in the url without adding a history entry.
+#[cfg(feature = "gui")]
+pub fn write_codes(codes: &Codes) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let r = (|| -> anyhow::Result<()> {
+            let window = web_sys::window().ok_or_else(|| anyhow::anyhow!("no window"))?;
+            let hash = window
+                .location()
+                .hash()
+                .map_err(crate::compute::into_jserr)?;
+            let mut params: Vec<String> = hash
+                .trim_start_matches('#')
+                .split('&')
+                .filter(|param| !param.is_empty() && !param.starts_with(CODES_PARAM))
+                .map(str::to_string)
+                .collect();
+            params.push(format!("{CODES_PARAM}{}", patch::to_packed(codes)?));
+            window
+                .history()
+                .map_err(crate::compute::into_jserr)?
+                .replace_state_with_url(
+                    &js_sys::wasm_bindgen::JsValue::NULL,
+                    "",
+                    Some(&format!("#{}", params.join("&"))),
+                )
+                .map_err(crate::compute::into_jserr)?;
+            Ok(())
+        })();
+        if let Err(e) = r {
+            log::warn!("unable to update url: {e:?}");
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = codes;
+}
+
+/// A link to the web build that opens with these codes.
+#[cfg(feature = "gui")]
+pub fn link(codes: &Codes) -> anyhow::Result<String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let location = web_sys::window()
+            .ok_or_else(|| anyhow::anyhow!("no window"))?
+            .location();
+        let origin = location.origin().map_err(crate::compute::into_jserr)?;
+        let path = location.pathname().map_err(crate::compute::into_jserr)?;
+        Ok(format!(
+            "{origin}{path}#{CODES_PARAM}{}",
+            patch::to_packed(codes)?
+        ))
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Ok(format!(
+        "{WEB_URL}#{CODES_PARAM}{}",
+        patch::to_packed(codes)?
+    ))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_codes_from_link() {
+        let codes = Codes::from_rows(&[vec![1, 2, 3], vec![4, 5, 1023]]).unwrap();
+        let packed = patch::to_packed(&codes).unwrap();
+        for link in [
+            format!("https://example.com/#codes={packed}"),
+            format!("https://example.com/#dev&codes={packed}&other"),
+        ] {
+            assert_eq!(codes_from_link(&link).unwrap(), codes, "{link}");
+        }
+        for link in [
+            "https://example.com/",
+            "https://example.com/#dev",
+            "https://example.com/#codes=!",
+        ] {
+            assert!(codes_from_link(link).is_err(), "{link}");
+        }
+    }
+}
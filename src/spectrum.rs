@@ -0,0 +1,97 @@
+//! Spectrograms of loops.
+
+use realfft::RealFftPlanner;
+
+/// Magnitudes below this are clamped, in dB.
+pub const FLOOR_DB: f32 = -120.0;
+
+/// Magnitudes of a loop over time, in dB relative to a full scale sine.
+pub struct Spectrogram {
+    sample_rate: u32,
+    /// Number of frequency bins in each column, from 0 up to the nyquist frequency.
+    bins: usize,
+    /// One column of `bins` magnitudes per hop.
+    db: Vec<f32>,
+}
+
+impl Spectrogram {
+    /// Short-time Fourier transform with a hann window. The samples are treated as a loop, so windows wrap around its end.
+    pub fn new(samples: &[f32], sample_rate: u32, window: usize, hop: usize) -> Self {
+        assert!(window >= 2);
+        assert!(hop >= 1);
+        let bins = window / 2 + 1;
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(window);
+        let weights: Vec<f32> = (0..window)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / window as f32).cos())
+            .collect();
+        // a full scale sine has a magnitude of window / 4 with a hann window
+        let scale = 4.0 / window as f32;
+        let mut input = fft.make_input_vec();
+        let mut output = fft.make_output_vec();
+        let mut scratch = fft.make_scratch_vec();
+        let mut db = Vec::new();
+        if !samples.is_empty() {
+            for start in (0..samples.len()).step_by(hop) {
+                for (i, (x, w)) in input.iter_mut().zip(&weights).enumerate() {
+                    *x = samples[(start + i) % samples.len()] * w;
+                }
+                fft.process_with_scratch(&mut input, &mut output, &mut scratch)
+                    .expect("buffers are made by the fft");
+                db.extend(
+                    output
+                        .iter()
+                        .map(|c| (20.0 * (c.norm() * scale).log10()).max(FLOOR_DB)),
+                );
+            }
+        }
+        Self {
+            sample_rate,
+            bins,
+            db,
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.db.len() / self.bins
+    }
+
+    /// Magnitude at `frequency` in Hz, interpolated between bins.
+    pub fn at(&self, column: usize, frequency: f32) -> f32 {
+        let column = &self.db[column * self.bins..(column + 1) * self.bins];
+        let bin = (frequency * 2.0 * (self.bins - 1) as f32 / self.sample_rate as f32)
+            .clamp(0.0, (self.bins - 1) as f32);
+        let (i, t) = (bin.floor() as usize, bin.fract());
+        match column.get(i + 1) {
+            Some(next) => column[i] * (1.0 - t) + next * t,
+            None => column[i],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_scale_sine_peaks_at_0_db() {
+        let (sample_rate, window) = (24000, 1024);
+        // centered on a bin, with a whole number of periods in the loop
+        let bin_width = sample_rate as f32 / window as f32;
+        let frequency = 64.0 * bin_width;
+        let samples: Vec<f32> = (0..sample_rate)
+            .map(|i| (std::f32::consts::TAU * frequency * i as f32 / sample_rate as f32).sin())
+            .collect();
+        let spectrogram = Spectrogram::new(&samples, sample_rate, window, 512);
+        assert!(spectrogram.columns() > 0);
+        for column in 0..spectrogram.columns() {
+            let peak = spectrogram.at(column, frequency);
+            assert!(peak.abs() < 0.1, "column {column}: {peak} dB");
+            for bin in (0..=window / 2).filter(|&b| b != 64) {
+                let db = spectrogram.at(column, bin as f32 * bin_width);
+                assert!(db < peak, "column {column}: bin {bin} at {db} dB");
+            }
+            // the hann window leaks into the neighbouring bins only
+            assert!(spectrogram.at(column, 60.0 * bin_width) < -60.0);
+        }
+    }
+}
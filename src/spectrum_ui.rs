@@ -0,0 +1,136 @@
+use egui::{pos2, Color32, ColorImage, Rect, TextureOptions};
+
+use crate::{compute, spectrum::Spectrogram};
+
+const WINDOW_SIZES: [usize; 5] = [256, 512, 1024, 2048, 4096];
+/// Lowest frequency shown, the highest is the nyquist frequency.
+const MIN_FREQUENCY: f32 = 20.0;
+const ROWS: usize = 256;
+/// Limits the texture size, the hop is increased for long loops.
+const MAX_COLUMNS: usize = 4096;
+const HEIGHT: f32 = 200.0;
+/// Colors from the lowest to the highest magnitude.
+const COLORS: [[u8; 3]; 5] = [
+    [0, 0, 0],
+    [30, 10, 90],
+    [130, 30, 130],
+    [235, 110, 40],
+    [250, 245, 160],
+];
+
+pub struct SpectrogramView {
+    window: usize,
+    hop: usize,
+    /// Magnitudes at or below this are black, in dB.
+    min_db: f32,
+    /// Generation of the samples and the settings the texture was made from.
+    source: Option<(u64, usize, usize, f32)>,
+    texture: Option<egui::TextureHandle>,
+}
+
+impl Default for SpectrogramView {
+    fn default() -> Self {
+        Self {
+            window: 1024,
+            hop: 128,
+            min_db: -90.0,
+            source: None,
+            texture: None,
+        }
+    }
+}
+
+fn color(t: f32) -> Color32 {
+    let x = t.clamp(0.0, 1.0) * (COLORS.len() - 1) as f32;
+    let i = (x.floor() as usize).min(COLORS.len() - 2);
+    let f = x - i as f32;
+    let [r, g, b] = std::array::from_fn(|c| {
+        (COLORS[i][c] as f32 * (1.0 - f) + COLORS[i + 1][c] as f32 * f).round() as u8
+    });
+    Color32::from_rgb(r, g, b)
+}
+
+/// Frequency of a row, on a log scale from the nyquist frequency at the top down to [`MIN_FREQUENCY`].
+fn row_frequency(row: f32) -> f32 {
+    let max = compute::SAMPLE_RATE as f32 / 2.0;
+    max * (MIN_FREQUENCY / max).powf(row / (ROWS - 1) as f32)
+}
+
+fn make_image(samples: &[f32], window: usize, hop: usize, min_db: f32) -> ColorImage {
+    let hop = hop.max(samples.len().div_ceil(MAX_COLUMNS));
+    let spectrogram = Spectrogram::new(samples, compute::SAMPLE_RATE, window, hop);
+    let columns = spectrogram.columns().max(1);
+    let frequencies: Vec<f32> = (0..ROWS).map(|row| row_frequency(row as f32)).collect();
+    let mut image = ColorImage::new([columns, ROWS], Color32::BLACK);
+    for x in 0..spectrogram.columns() {
+        for (y, &frequency) in frequencies.iter().enumerate() {
+            let db = spectrogram.at(x, frequency);
+            image[(x, y)] = color((db - min_db) / -min_db);
+        }
+    }
+    image
+}
+
+/// `generation` changes whenever `samples` do, so they don't have to be compared.
+pub fn draw(ui: &mut egui::Ui, view: &mut SpectrogramView, samples: &[f32], generation: u64) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("window")
+            .selected_text(view.window.to_string())
+            .show_ui(ui, |ui| {
+                for size in WINDOW_SIZES {
+                    ui.selectable_value(&mut view.window, size, size.to_string());
+                }
+            });
+        ui.add(
+            egui::DragValue::new(&mut view.hop)
+                .range(16..=view.window)
+                .prefix("hop "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut view.min_db)
+                .range(-160.0..=-20.0)
+                .suffix(" dB"),
+        )
+        .on_hover_text("lowest magnitude shown");
+    });
+    view.hop = view.hop.min(view.window);
+    let source = (generation, view.window, view.hop, view.min_db);
+    let up_to_date = view.source == Some(source);
+    if !up_to_date {
+        let image = make_image(samples, view.window, view.hop, view.min_db);
+        view.texture = Some(
+            ui.ctx()
+                .load_texture("spectrogram", image, TextureOptions::LINEAR),
+        );
+        view.source = Some(source);
+    }
+    let (_, rect) = ui.allocate_space(egui::vec2(ui.available_width(), HEIGHT));
+    let painter = ui.painter_at(rect);
+    if let Some(texture) = &view.texture {
+        painter.image(
+            texture.id(),
+            rect,
+            Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+            Color32::WHITE,
+        );
+    }
+    // frequency axis
+    let max = compute::SAMPLE_RATE as f32 / 2.0;
+    for frequency in [50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0] {
+        let row = (frequency / max).ln() / (MIN_FREQUENCY / max).ln();
+        let y = rect.top() + row * rect.height();
+        let label = if frequency >= 1000.0 {
+            format!("{}k", frequency / 1000.0)
+        } else {
+            format!("{frequency}")
+        };
+        painter.hline(rect.left()..=rect.left() + 4.0, y, (1.0, Color32::GRAY));
+        painter.text(
+            pos2(rect.left() + 6.0, y),
+            egui::Align2::LEFT_CENTER,
+            label,
+            egui::FontId::monospace(10.0),
+            Color32::GRAY,
+        );
+    }
+}
@@ -1,73 +1,239 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
+use crossbeam::atomic::AtomicCell;
+#[cfg(not(target_arch = "wasm32"))]
+use crossbeam::channel::{Receiver, Sender};
+
+#[cfg(feature = "audio")]
 use crate::audio;
+use crate::{compute, resample};
 
-struct State {
-    current_sample_rate: u32,
+pub const DEFAULT_CROSSFADE: f32 = 0.05;
+
+struct Resampled {
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+/// A loop that is playing, possibly fading in or out.
+struct Voice {
+    samples: Vec<f32>,
     play_pos: usize,
+    gain: f32,
+}
+
+struct State {
+    sample_rate: u32,
+    /// The last voice is the current loop, any others are fading out.
+    voices: Vec<Voice>,
+}
+
+/// Work for the [`Resampler`].
+enum Job {
+    /// A new loop at [`compute::SAMPLE_RATE`].
+    Samples(Vec<f32>),
+    /// The device sample rate changed.
+    SampleRate(u32),
+}
+
+/// Resamples the loop to the device sample rate, and hands the result to the audio thread.
+struct Resampler {
+    /// The loop at [`compute::SAMPLE_RATE`], kept so it can be resampled again if the device sample rate changes.
     raw_samples: Option<Vec<f32>>,
-    resampled_samples: Option<Vec<f32>>,
+    sample_rate: u32,
+    incoming: Arc<Mutex<Option<Resampled>>>,
+}
+
+impl Resampler {
+    fn handle(&mut self, job: Job) {
+        match job {
+            Job::Samples(samples) => self.raw_samples = Some(samples),
+            Job::SampleRate(sample_rate) => self.sample_rate = sample_rate,
+        }
+    }
+
+    fn resample(&self) {
+        let sample_rate = self.sample_rate;
+        if sample_rate == 0 {
+            return;
+        }
+        if let Some(raw) = &self.raw_samples {
+            let samples = resample::resample(raw, compute::SAMPLE_RATE, sample_rate, true);
+            *self.incoming.lock().unwrap() = Some(Resampled {
+                sample_rate,
+                samples,
+            });
+        }
+    }
+
+    /// Handle jobs until the player is dropped, skipping loops that have already been replaced.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run(mut self, jobs: Receiver<Job>) {
+        while let Ok(job) = jobs.recv() {
+            self.handle(job);
+            for job in jobs.try_iter() {
+                self.handle(job);
+            }
+            self.resample();
+        }
+    }
 }
 
 pub struct SamplePlayer {
-    incoming: Mutex<Option<Vec<f32>>>,
+    /// Sample rate of the output device, 0 until the first callback.
+    device_sample_rate: AtomicCell<u32>,
+    /// Sample rate the loop was last sent to be resampled at.
+    resampled_rate: AtomicCell<u32>,
+    /// Time in seconds to crossfade from the old loop when a new one arrives.
+    crossfade: AtomicCell<f32>,
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: Sender<Job>,
+    /// There are no threads on the web, so the loop is resampled on the calling thread there.
+    #[cfg(target_arch = "wasm32")]
+    resampler: Mutex<Resampler>,
+    incoming: Arc<Mutex<Option<Resampled>>>,
     // TODO: don't share the player between threads, so we can avoid this mutex
     state: Mutex<Option<State>>,
 }
 
+impl Default for SamplePlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SamplePlayer {
     pub fn new() -> Self {
+        let incoming = Arc::new(Mutex::new(None));
+        let resampler = Resampler {
+            raw_samples: None,
+            sample_rate: 0,
+            incoming: incoming.clone(),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let jobs = {
+            let (jobs, receiver) = crossbeam::channel::unbounded();
+            std::thread::Builder::new()
+                .name("resampler".to_string())
+                .spawn(move || resampler.run(receiver))
+                .expect("unable to start the resampler thread");
+            jobs
+        };
         Self {
-            incoming: Mutex::new(None),
+            device_sample_rate: AtomicCell::new(0),
+            resampled_rate: AtomicCell::new(0),
+            crossfade: AtomicCell::new(DEFAULT_CROSSFADE),
+            #[cfg(not(target_arch = "wasm32"))]
+            jobs,
+            #[cfg(target_arch = "wasm32")]
+            resampler: Mutex::new(resampler),
+            incoming,
             state: Mutex::new(None),
         }
     }
 
+    /// Set a new loop at [`compute::SAMPLE_RATE`]. It is resampled on a separate thread, and played once that is done.
     pub fn update_samples(&self, samples: Vec<f32>) {
-        *self.incoming.lock().unwrap() = Some(samples);
+        self.send(Job::Samples(samples));
+        self.update();
+    }
+
+    pub fn set_crossfade(&self, seconds: f32) {
+        self.crossfade.store(seconds.max(0.0));
+    }
+
+    /// Resample the loop if the device sample rate has changed.
+    /// Should be called regularly from some thread other than the audio thread.
+    pub fn update(&self) {
+        let sample_rate = self.device_sample_rate.load();
+        if sample_rate != self.resampled_rate.swap(sample_rate) {
+            self.send(Job::SampleRate(sample_rate));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn send(&self, job: Job) {
+        // the resampler only stops when the player is dropped
+        let _ = self.jobs.send(job);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn send(&self, job: Job) {
+        let mut resampler = self.resampler.lock().unwrap();
+        resampler.handle(job);
+        resampler.resample();
     }
 }
 
+#[cfg(feature = "audio")]
 impl audio::Synth for SamplePlayer {
     fn play(&self, sample_rate: u32, channels: usize, out_samples: &mut [f32]) {
+        SamplePlayer::play(self, sample_rate, channels, out_samples);
+    }
+}
+
+impl SamplePlayer {
+    /// Fill `out_samples` with interleaved frames of `channels` channels at `sample_rate`.
+    pub fn play(&self, sample_rate: u32, channels: usize, out_samples: &mut [f32]) {
+        if self.device_sample_rate.swap(sample_rate) != sample_rate {
+            log::info!("sample rate changed to: {sample_rate}");
+        }
         let mut state = self.state.lock().unwrap();
         let sref = state.get_or_insert_with(|| State {
-            current_sample_rate: 0,
-            play_pos: 0,
-            raw_samples: None,
-            resampled_samples: None,
+            sample_rate,
+            voices: Vec::new(),
         });
+        let fade_len = (self.crossfade.load() * sample_rate as f32) as usize;
         if let Some(incoming) = self.incoming.lock().unwrap().take() {
-            sref.raw_samples = Some(incoming);
-            sref.resampled_samples = None;
-        }
-        if sref.current_sample_rate != sample_rate {
-            log::info!("sample rate changed to: {sample_rate}");
-            sref.resampled_samples = None;
-            sref.current_sample_rate = sample_rate;
-        }
-        if sref.resampled_samples.is_none() {
-            sref.play_pos = 0;
-            const ENCODEC_SAMPLE_RATE: usize = 24000;
-            if let Some(raw) = &sref.raw_samples {
-                // TODO: handle looping better?
-                // TODO: do some proper resampling. using rubato?
-                let ratio = sref.current_sample_rate as f64 / ENCODEC_SAMPLE_RATE as f64;
-                let output_size = (raw.len() as f64 * ratio) as usize;
-                let mut resampled = sref.resampled_samples.take().unwrap_or_default();
-                resampled.resize(output_size, 0f32);
-                for (i, s) in resampled.iter_mut().enumerate() {
-                    *s = raw[(i as f64 / ratio) as usize];
+            match sref.voices.last() {
+                Some(current) if sref.sample_rate == incoming.sample_rate && fade_len > 0 => {
+                    // keep the phase within the loop, so that it sounds continuous even if the length changes
+                    let phase = current.play_pos as f64 / current.samples.len() as f64;
+                    sref.voices.push(Voice {
+                        play_pos: (phase * incoming.samples.len() as f64) as usize,
+                        samples: incoming.samples,
+                        gain: 0.0,
+                    });
+                }
+                current => {
+                    let phase = current
+                        .filter(|_| sref.sample_rate == incoming.sample_rate)
+                        .map_or(0.0, |c| c.play_pos as f64 / c.samples.len() as f64);
+                    sref.voices = vec![Voice {
+                        play_pos: (phase * incoming.samples.len() as f64) as usize,
+                        samples: incoming.samples,
+                        gain: 1.0,
+                    }];
+                    sref.sample_rate = incoming.sample_rate;
                 }
-                sref.resampled_samples = Some(resampled);
             }
+            sref.voices.retain(|v| !v.samples.is_empty());
         }
-        // TODO: do the resampling on the background thread instead
+        // wait for the loop to be resampled if the sample rate has changed
+        let playing = sref.sample_rate == sample_rate;
+        let step = 1.0 / fade_len.max(1) as f32;
         for s in out_samples.chunks_exact_mut(channels) {
-            let value = if let Some(data) = &sref.resampled_samples {
-                //let value = if let Some(data) = &sref.raw_samples {
-                sref.play_pos = (sref.play_pos + 1) % data.len();
-                data[sref.play_pos]
+            let value = if playing && !sref.voices.is_empty() {
+                let current = sref.voices.len() - 1;
+                let mut sum = 0.0;
+                let mut gain_sum = 0.0;
+                for (i, voice) in sref.voices.iter_mut().enumerate() {
+                    voice.play_pos = (voice.play_pos + 1) % voice.samples.len();
+                    voice.gain = if i == current {
+                        (voice.gain + step).min(1.0)
+                    } else {
+                        (voice.gain - step).max(0.0)
+                    };
+                    sum += voice.samples[voice.play_pos] * voice.gain;
+                    gain_sum += voice.gain;
+                }
+                // the current voice always has some gain at this point
+                sref.voices.retain(|v| v.gain > 0.0);
+                if gain_sum > 0.0 {
+                    sum / gain_sum
+                } else {
+                    0.0
+                }
             } else {
                 0.0
             };
@@ -77,3 +243,97 @@ impl audio::Synth for SamplePlayer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    /// Hand `samples` to the audio thread as if they had been resampled to [`RATE`].
+    fn queue(player: &SamplePlayer, samples: Vec<f32>) {
+        *player.incoming.lock().unwrap() = Some(Resampled {
+            sample_rate: RATE,
+            samples,
+        });
+    }
+
+    /// Play `frames` stereo frames, checking that both channels are the same.
+    fn play(player: &SamplePlayer, frames: usize) -> Vec<f32> {
+        let mut out = vec![f32::NAN; frames * 2];
+        player.play(RATE, 2, &mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+        out.into_iter().step_by(2).collect()
+    }
+
+    #[test]
+    fn crossfade_ramps_over_its_length() {
+        let player = SamplePlayer::new();
+        // 10 frames
+        player.set_crossfade(0.01);
+        queue(&player, vec![1.0; 100]);
+        assert_eq!(play(&player, 20), [1.0; 20]);
+        queue(&player, vec![-1.0; 100]);
+        let out = play(&player, 20);
+        for (i, value) in out.iter().enumerate() {
+            let expected = (1.0 - 0.2 * (i + 1) as f32).max(-1.0);
+            assert!(
+                (value - expected).abs() < 1e-5,
+                "frame {i}: {value} instead of {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn new_loop_starts_at_the_phase_of_the_old_one() {
+        for crossfade in [0.0, 0.01] {
+            let player = SamplePlayer::new();
+            player.set_crossfade(crossfade);
+            queue(&player, (0..100).map(|i| i as f32).collect());
+            play(&player, 25);
+            // a quarter of the way through the old loop, so a quarter of the way through the new one, which is twice as long
+            queue(&player, (0..200).map(|i| (1000 + i) as f32).collect());
+            let out = play(&player, 20);
+            if crossfade == 0.0 {
+                assert_eq!(out[0], 1051.0);
+            }
+            // once the old loop has faded out
+            assert_eq!(
+                out[9..],
+                (60..71).map(|i| (1000 + i) as f32).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn no_crossfade_switches_immediately() {
+        let player = SamplePlayer::new();
+        player.set_crossfade(0.05);
+        queue(&player, vec![1.0; 100]);
+        play(&player, 10);
+        // interrupt a crossfade, the voices fading out should be dropped as well
+        queue(&player, vec![-1.0; 100]);
+        play(&player, 10);
+        player.set_crossfade(0.0);
+        queue(&player, vec![0.5; 100]);
+        assert_eq!(play(&player, 20), [0.5; 20]);
+    }
+
+    #[test]
+    fn new_loops_are_played_once_resampled() {
+        let player = SamplePlayer::new();
+        // the first callback tells the player the device sample rate
+        let mut out = vec![0.0; 10];
+        player.play(compute::SAMPLE_RATE, 1, &mut out);
+        player.update_samples(vec![0.5; 100]);
+        let start = std::time::Instant::now();
+        while out[0] == 0.0 {
+            assert!(start.elapsed().as_secs() < 10, "the loop was never played");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            player.play(compute::SAMPLE_RATE, 1, &mut out);
+        }
+        assert_eq!(out, [0.5; 10]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::Cursor;
+
+use crate::{compute, resample};
+
+/// Read a wav file, mixing it down to mono and resampling it to [`compute::SAMPLE_RATE`].
+pub fn read(bytes: &[u8]) -> anyhow::Result<Vec<f32>> {
+    let mut reader = hound::WavReader::new(Cursor::new(bytes))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    let channels = usize::from(spec.channels);
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok(resample::resample(
+        &mono,
+        spec.sample_rate,
+        compute::SAMPLE_RATE,
+        false,
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Int16,
+    Float32,
+}
+
+/// Write mono samples to an in-memory wav file.
+pub fn write(samples: &[f32], sample_rate: u32, format: Format) -> anyhow::Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: match format {
+            Format::Int16 => 16,
+            Format::Float32 => 32,
+        },
+        sample_format: match format {
+            Format::Int16 => hound::SampleFormat::Int,
+            Format::Float32 => hound::SampleFormat::Float,
+        },
+    };
+    let mut data = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut data, spec)?;
+    for &s in samples {
+        match format {
+            Format::Int16 => writer.write_sample((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?,
+            Format::Float32 => writer.write_sample(s)?,
+        }
+    }
+    writer.finalize()?;
+    Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A wav file of interleaved `samples`, in the format of `spec`.
+    fn wav<S: hound::Sample + Copy>(spec: hound::WavSpec, samples: &[S]) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut data, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+        data.into_inner()
+    }
+
+    fn spec(
+        channels: u16,
+        sample_rate: u32,
+        bits: u16,
+        format: hound::SampleFormat,
+    ) -> hound::WavSpec {
+        hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: bits,
+            sample_format: format,
+        }
+    }
+
+    #[test]
+    fn written_files_read_back() {
+        let samples: Vec<f32> = (0..100).map(|i| (i as f32 * 0.1).sin() * 0.9).collect();
+        let float = read(&write(&samples, compute::SAMPLE_RATE, Format::Float32).unwrap()).unwrap();
+        assert_eq!(float, samples);
+        let int = read(&write(&samples, compute::SAMPLE_RATE, Format::Int16).unwrap()).unwrap();
+        assert_eq!(int.len(), samples.len());
+        for (a, b) in int.iter().zip(&samples) {
+            assert!((a - b).abs() < 1e-4, "{a} instead of {b}");
+        }
+        let reader =
+            hound::WavReader::new(Cursor::new(write(&samples, 48000, Format::Int16).unwrap()))
+                .unwrap();
+        assert_eq!(reader.spec(), spec(1, 48000, 16, hound::SampleFormat::Int));
+    }
+
+    #[test]
+    fn integers_are_scaled_to_unit_range() {
+        for bits in [8, 16, 24, 32] {
+            let full = 1i64 << (bits - 1);
+            let samples = [-full, -full / 2, 0, full / 2, full - 1].map(|s| s as i32);
+            let data = wav(
+                spec(1, compute::SAMPLE_RATE, bits, hound::SampleFormat::Int),
+                &samples,
+            );
+            let read = read(&data).unwrap();
+            let expected = [-1.0, -0.5, 0.0, 0.5, 1.0];
+            assert_eq!(read.len(), expected.len());
+            for (a, b) in read.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-2, "{bits} bits: {a} instead of {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn stereo_is_mixed_down() {
+        let data = wav(
+            spec(2, compute::SAMPLE_RATE, 32, hound::SampleFormat::Float),
+            &[0.5f32, -0.25, 1.0, 0.0],
+        );
+        assert_eq!(read(&data).unwrap(), [0.125, 0.5]);
+    }
+
+    #[test]
+    fn other_rates_are_resampled() {
+        let sine = |rate: u32, len: usize| -> Vec<f32> {
+            (0..len)
+                .map(|i| (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin() * 0.5)
+                .collect()
+        };
+        let data = wav(
+            spec(1, 48000, 32, hound::SampleFormat::Float),
+            &sine(48000, 4800),
+        );
+        let read = read(&data).unwrap();
+        assert_eq!(read.len(), 2400);
+        // away from the ends, where the kernel runs out of input
+        let expected = sine(compute::SAMPLE_RATE, 2400);
+        for i in 100..2300 {
+            assert!(
+                (read[i] - expected[i]).abs() < 1e-3,
+                "sample {i}: {} instead of {}",
+                read[i],
+                expected[i]
+            );
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Runs the model off the UI thread.
+//!
+//! Natively this is a background thread, on the web it is a web worker running the `decode-worker` binary.
+//! Only one request is in flight at a time. Queued decode requests are coalesced so only the latest codes or latent get decoded,
+//! and queued candidates of older generations are dropped.
+
+use std::collections::VecDeque;
+
+use candle_core::IndexOp as _;
+use crossbeam::channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codes::Codes,
+    compute::{Compute, Contribution, LatentStats, LoopMode, FRAGMENT_SIZE},
+    latent::{Latent, AXES},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    Decode {
+        codes: Codes,
+        mode: LoopMode,
+    },
+    /// Decode a candidate of the population in [`crate::evolution`].
+    DecodeCandidate {
+        generation: usize,
+        index: usize,
+        codes: Codes,
+        mode: LoopMode,
+    },
+    /// Decode a variant of the codes with codebooks left out, see [`crate::contribution_ui`].
+    /// One per codebook, so that they can be dropped when a new analysis starts.
+    DecodeVariant {
+        codes: Codes,
+        mode: LoopMode,
+        contribution: Contribution,
+        codebook: Option<usize>,
+    },
+    /// Decode a latent directly, see [`crate::latent`].
+    DecodeLatent {
+        latent: Latent,
+        mode: LoopMode,
+    },
+    /// Convert codes to a latent.
+    Latent(Codes),
+    LatentStats,
+    /// Order the codes of each codebook by similarity.
+    Orderings,
+    /// Encode mono samples at [`crate::compute::SAMPLE_RATE`], keeping at most `codebooks` codebooks and `max_frames` frames.
+    Encode {
+        samples: Vec<f32>,
+        codebooks: usize,
+        max_frames: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    Loaded,
+    LoadFailed(String),
+    Decoded {
+        codes: Codes,
+        mode: LoopMode,
+        samples: Vec<f32>,
+    },
+    /// Failures are returned here rather than as [`Response::Error`], so they can be told apart per candidate.
+    DecodedCandidate {
+        generation: usize,
+        index: usize,
+        samples: Result<Vec<f32>, String>,
+    },
+    /// Failures are returned here, like for candidates, so that the analysis can show them.
+    DecodedVariant {
+        codes: Codes,
+        mode: LoopMode,
+        contribution: Contribution,
+        codebook: Option<usize>,
+        samples: Result<Vec<f32>, String>,
+    },
+    DecodedLatent {
+        latent: Latent,
+        mode: LoopMode,
+        samples: Vec<f32>,
+    },
+    Latent(Latent),
+    LatentStats(LatentStats),
+    Orderings(Vec<Vec<u32>>),
+    Encoded(Codes),
+    Error(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum State {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+fn handle(compute: &Compute, request: Request) -> Response {
+    let r = match request {
+        Request::Decode { codes, mode } => (|| {
+            let samples = compute.decode_codes(&codes.to_tensor(compute.device())?, mode)?;
+            Ok(Response::Decoded {
+                codes,
+                mode,
+                samples,
+            })
+        })(),
+        Request::DecodeCandidate {
+            generation,
+            index,
+            codes,
+            mode,
+        } => {
+            let samples = (|| compute.decode_codes(&codes.to_tensor(compute.device())?, mode))()
+                .map_err(|e| {
+                    log::warn!("unable to decode candidate {index}: {e:?}");
+                    format!("{e:#}")
+                });
+            Ok(Response::DecodedCandidate {
+                generation,
+                index,
+                samples,
+            })
+        }
+        Request::DecodeVariant {
+            codes,
+            mode,
+            contribution,
+            codebook,
+        } => {
+            let samples = (|| {
+                let codes = codes.to_tensor(compute.device())?;
+                compute.decode_variant(&codes, mode, contribution, codebook)
+            })()
+            .map_err(|e| {
+                log::warn!("unable to decode variant {codebook:?}: {e:?}");
+                format!("{e:#}")
+            });
+            Ok(Response::DecodedVariant {
+                codes,
+                mode,
+                contribution,
+                codebook,
+                samples,
+            })
+        }
+        Request::DecodeLatent { latent, mode } => (|| {
+            let samples = compute.decode_latent(&latent.to_tensor(compute.device())?, mode)?;
+            Ok(Response::DecodedLatent {
+                latent,
+                mode,
+                samples,
+            })
+        })(),
+        Request::Latent(codes) => (|| {
+            let latent = compute.codes_to_latent(&codes.to_tensor(compute.device())?)?;
+            Ok(Response::Latent(Latent::from_tensor(&latent)?))
+        })(),
+        Request::LatentStats => compute.latent_stats(AXES).map(Response::LatentStats),
+        Request::Orderings => compute.code_orderings().map(Response::Orderings),
+        Request::Encode {
+            samples,
+            codebooks,
+            max_frames,
+        } => (|| {
+            // no need to encode more than is kept
+            let samples = &samples[..samples.len().min(max_frames * FRAGMENT_SIZE)];
+            let codes = compute.encode_samples(samples)?;
+            let codebooks = codebooks.min(codes.dim(0)?);
+            let frames = max_frames.min(codes.dim(1)?);
+            Ok(Response::Encoded(Codes::from_tensor(
+                &codes.i((..codebooks, ..frames))?,
+            )?))
+        })(),
+    };
+    r.unwrap_or_else(|e: anyhow::Error| {
+        log::warn!("worker error: {e:?}");
+        Response::Error(format!("{e:#}"))
+    })
+}
+
+pub struct Worker {
+    state: State,
+    queue: VecDeque<Request>,
+    in_flight: bool,
+    responses: Receiver<Response>,
+    backend: Backend,
+}
+
+impl Worker {
+    /// Start the worker and begin loading the model. `ctx` is repainted whenever a response arrives.
+    pub fn spawn(ctx: egui::Context) -> Self {
+        let (response_tx, responses) = crossbeam::channel::unbounded();
+        let (state, backend) = match Backend::spawn(ctx, response_tx) {
+            Ok(backend) => (State::Loading, backend),
+            Err(e) => {
+                log::warn!("unable to start worker: {e:?}");
+                (
+                    State::Failed(format!("unable to start worker: {e:#}")),
+                    Backend::None,
+                )
+            }
+        };
+        Self {
+            state,
+            queue: VecDeque::new(),
+            in_flight: false,
+            responses,
+            backend,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn request(&mut self, request: Request) {
+        if matches!(request, Request::Decode { .. }) {
+            self.queue.retain(|r| !matches!(r, Request::Decode { .. }));
+        }
+        if matches!(request, Request::DecodeLatent { .. }) {
+            self.queue
+                .retain(|r| !matches!(r, Request::DecodeLatent { .. }));
+        }
+        if let Request::DecodeVariant {
+            codes,
+            mode,
+            contribution,
+            ..
+        } = &request
+        {
+            // variants of earlier analyses are not needed anymore
+            self.queue.retain(|r| {
+                !matches!(r, Request::DecodeVariant { codes: c, mode: m, contribution: k, .. }
+                    if (c, m, k) != (codes, mode, contribution))
+            });
+        }
+        if let Request::DecodeCandidate { generation, .. } = request {
+            // candidates of older generations are not needed anymore
+            self.queue.retain(|r| {
+                !matches!(r, Request::DecodeCandidate { generation: g, .. } if *g != generation)
+            });
+        }
+        if matches!(request, Request::DecodeVariant { .. }) {
+            self.queue.push_back(request);
+        } else {
+            // an analysis can take many decodes, so let everything else go first
+            let first_variant = self
+                .queue
+                .iter()
+                .position(|r| matches!(r, Request::DecodeVariant { .. }))
+                .unwrap_or(self.queue.len());
+            self.queue.insert(first_variant, request);
+        }
+        self.send_next();
+    }
+
+    /// Returns the next response to a request.
+    pub fn try_recv(&mut self) -> Option<Response> {
+        loop {
+            let response = self.responses.try_recv().ok()?;
+            match response {
+                Response::Loaded => {
+                    self.state = State::Loaded;
+                    self.send_next();
+                }
+                Response::LoadFailed(e) => {
+                    self.state = State::Failed(e);
+                }
+                response => {
+                    self.in_flight = false;
+                    self.send_next();
+                    return Some(response);
+                }
+            }
+        }
+    }
+
+    fn send_next(&mut self) {
+        if self.in_flight || self.state != State::Loaded {
+            return;
+        }
+        if let Some(request) = self.queue.pop_front() {
+            match self.backend.send(request) {
+                Ok(()) => self.in_flight = true,
+                Err(e) => {
+                    log::warn!("unable to send request to worker: {e:?}");
+                    self.state = State::Failed(format!("worker stopped: {e:#}"));
+                }
+            }
+        }
+    }
+}
+
+enum Backend {
+    None,
+    #[cfg(not(target_arch = "wasm32"))]
+    Thread(Sender<Request>),
+    #[cfg(target_arch = "wasm32")]
+    Web {
+        worker: web_sys::Worker,
+        _onmessage: js_sys::wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+    },
+}
+
+impl Backend {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn(ctx: egui::Context, responses: Sender<Response>) -> anyhow::Result<Self> {
+        let (request_tx, requests) = crossbeam::channel::unbounded::<Request>();
+        std::thread::Builder::new()
+            .name("worker".to_string())
+            .spawn(move || {
+                let send = |response| {
+                    // the receiver is only gone if the app is shutting down
+                    let _ = responses.send(response);
+                    ctx.request_repaint();
+                };
+                let compute = match pollster::block_on(Compute::new()) {
+                    Ok(compute) => compute,
+                    Err(e) => {
+                        log::warn!("unable to load model: {e:?}");
+                        send(Response::LoadFailed(format!("{e:#}")));
+                        return;
+                    }
+                };
+                send(Response::Loaded);
+                for request in requests {
+                    send(handle(&compute, request));
+                }
+            })?;
+        Ok(Self::Thread(request_tx))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn(ctx: egui::Context, responses: Sender<Response>) -> anyhow::Result<Self> {
+        use js_sys::wasm_bindgen::{closure::Closure, JsCast as _};
+
+        let worker = web_sys::Worker::new("./decode-worker_loader.js")
+            .map_err(crate::compute::into_jserr)?;
+        let onmessage =
+            Closure::<dyn FnMut(_)>::new(move |event: web_sys::MessageEvent| {
+                match event
+                    .data()
+                    .as_string()
+                    .ok_or_else(|| anyhow::anyhow!("expected a string"))
+                    .and_then(|data| Ok(serde_json::from_str::<Response>(&data)?))
+                {
+                    Ok(response) => {
+                        let _ = responses.send(response);
+                        ctx.request_repaint();
+                    }
+                    Err(e) => log::warn!("invalid message from worker: {e:?}"),
+                }
+            });
+        worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        Ok(Self::Web {
+            worker,
+            _onmessage: onmessage,
+        })
+    }
+
+    fn send(&self, request: Request) -> anyhow::Result<()> {
+        match self {
+            Backend::None => anyhow::bail!("no worker"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Backend::Thread(requests) => Ok(requests.send(request)?),
+            #[cfg(target_arch = "wasm32")]
+            Backend::Web { worker, .. } => {
+                worker
+                    .post_message(&serde_json::to_string(&request)?.into())
+                    .map_err(crate::compute::into_jserr)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Backend::Web { worker, .. } = self {
+            worker.terminate();
+        }
+    }
+}
+
+/// Entry point of the `decode-worker` binary.
+#[cfg(target_arch = "wasm32")]
+pub fn web_worker_main() {
+    use std::{cell::RefCell, rc::Rc};
+
+    use js_sys::wasm_bindgen::{closure::Closure, JsCast as _};
+
+    let scope: web_sys::DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+    let post = {
+        let scope = scope.clone();
+        move |response: Response| {
+            let r = serde_json::to_string(&response)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| {
+                    scope
+                        .post_message(&data.into())
+                        .map_err(|e| crate::compute::into_jserr(e).into())
+                });
+            if let Err(e) = r {
+                log::warn!("unable to post response: {e:?}");
+            }
+        }
+    };
+    let compute = Rc::new(RefCell::new(None));
+    {
+        let compute = compute.clone();
+        let post = post.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match Compute::new().await {
+                Ok(c) => {
+                    *compute.borrow_mut() = Some(c);
+                    post(Response::Loaded);
+                }
+                Err(e) => {
+                    log::warn!("unable to load model: {e:?}");
+                    post(Response::LoadFailed(format!("{e:#}")));
+                }
+            }
+        });
+    }
+    let onmessage = Closure::<dyn FnMut(_)>::new(move |event: web_sys::MessageEvent| {
+        let response = match event
+            .data()
+            .as_string()
+            .ok_or_else(|| anyhow::anyhow!("expected a string"))
+            .and_then(|data| Ok(serde_json::from_str::<Request>(&data)?))
+        {
+            Ok(request) => match compute.borrow().as_ref() {
+                Some(compute) => handle(compute, request),
+                None => Response::Error("model not loaded".to_string()),
+            },
+            Err(e) => Response::Error(format!("invalid request: {e:#}")),
+        };
+        post(response);
+    });
+    scope.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    // the worker lives until it is terminated
+    onmessage.forget();
+}